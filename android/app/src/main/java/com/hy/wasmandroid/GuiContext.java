@@ -10,7 +10,7 @@ import java.util.HashMap;
 import java.util.Map;
 
 public class GuiContext {
-    private static native int JNIButtonPress(int id) throws Exception;
+    private static native int JNIButtonPress(long runtime, int id) throws Exception;
 
     private Map<ElementType, Integer> counters = new EnumMap<>(ElementType.class);
     private Map<Integer, TextView> textViews = new HashMap<>();
@@ -18,11 +18,13 @@ public class GuiContext {
     private Map<Integer, GameCanvas> canvases = new HashMap<>();
     private Map<Integer, GameCanvas.Sprite> sprites = new HashMap<>();
     private Activity activity;
+    private Wasm wasm;
     private LinearLayout.LayoutParams params;
     private LinearLayout linearLayout;
 
-    public GuiContext(Activity activity) {
+    public GuiContext(Activity activity, Wasm wasm) {
         this.activity = activity;
+        this.wasm = wasm;
         this.params = new LinearLayout.LayoutParams(LinearLayout.LayoutParams.MATCH_PARENT, LinearLayout.LayoutParams.WRAP_CONTENT);
         this.linearLayout = new LinearLayout(activity);
         linearLayout.setOrientation(LinearLayout.VERTICAL);
@@ -59,7 +61,7 @@ public class GuiContext {
         button.setOnClickListener(v -> {
             System.out.println("Button "+ id +" pressed;");
             try {
-                JNIButtonPress(id);
+                JNIButtonPress(wasm.getRuntime(), id);
             } catch(Exception e) {
                 e.printStackTrace();
             }
@@ -12,21 +12,27 @@ import java.util.function.Function;
 
 public class Wasm {
 
-    private static native void JNIInitializeRuntime(Wasm self, String cacheDir) throws Exception;
-    private static native void JNIRunMainWASM() throws Exception;
-    private static native void JNIOnTick() throws Exception;
+    private static native long JNIInitializeRuntime(Wasm self, String cacheDir) throws Exception;
+    private static native void JNIRunMainWASM(long runtime) throws Exception;
+    private static native void JNIOnTick(long runtime) throws Exception;
 
     private MainActivity context;
     private GuiContext guiContext;
+    // The native runtime, which lives as long as the application.
+    private long runtime;
 
     Wasm(MainActivity context) {
         this.context = context;
-        this.guiContext = new GuiContext(context);
+        this.guiContext = new GuiContext(context, this);
+    }
+
+    long getRuntime() {
+        return runtime;
     }
 
     public void initWASM() throws Exception {
         String cacheDir = context.getCacheDir().getAbsolutePath();
-        JNIInitializeRuntime(this, cacheDir);
+        runtime = JNIInitializeRuntime(this, cacheDir);
 
 
         new Thread(() -> {
@@ -36,7 +42,7 @@ public class Wasm {
             AtomicBoolean ready = new AtomicBoolean(false);
             Runnable updater = () -> {
                 try {
-                    JNIOnTick();
+                    JNIOnTick(runtime);
                 } catch (Exception e) {
                     e.printStackTrace();
                 }
@@ -76,7 +82,7 @@ public class Wasm {
     }
 
     public void runWASM() throws Exception {
-        JNIRunMainWASM();
+        JNIRunMainWASM(runtime);
     }
 
     @Keep
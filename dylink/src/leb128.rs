@@ -0,0 +1,314 @@
+//! LEB128 integers as used throughout the WebAssembly binary format.
+//!
+//! `decode_*` read from the start of a slice and return the value together with the
+//! number of bytes it took, `read_*` and `write_*` work on `std::io` readers and writers.
+use std::fmt;
+use std::io::{self, Read, Write};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Leb128Error {
+    /// The input ended before the last byte of the number.
+    Truncated,
+    /// The number takes more bytes than its type allows or does not fit the type.
+    Overlong,
+}
+
+impl fmt::Display for Leb128Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Leb128Error::Truncated => write!(f, "LEB128 number is truncated"),
+            Leb128Error::Overlong => write!(f, "LEB128 number is overlong or out of range"),
+        }
+    }
+}
+
+impl std::error::Error for Leb128Error {}
+
+impl From<Leb128Error> for io::Error {
+    fn from(e: Leb128Error) -> Self {
+        let kind = match e {
+            Leb128Error::Truncated => io::ErrorKind::UnexpectedEof,
+            Leb128Error::Overlong => io::ErrorKind::InvalidData,
+        };
+        io::Error::new(kind, e)
+    }
+}
+
+/// Accumulates the bytes of one number of `bits` bits.
+struct Decoder {
+    bits: u32,
+    signed: bool,
+    value: u64,
+    shift: u32,
+}
+
+impl Decoder {
+    fn new(bits: u32, signed: bool) -> Self {
+        Self {
+            bits,
+            signed,
+            value: 0,
+            shift: 0,
+        }
+    }
+
+    /// Adds the next byte, returning the value once it is complete.
+    fn push(&mut self, byte: u8) -> Result<Option<u64>, Leb128Error> {
+        let payload = (byte & 0x7f) as u64;
+        let remaining = self.bits - self.shift;
+        if remaining <= 7 {
+            // The last byte the type allows: it must end the number, and the bits
+            // beyond the type must be zero, or copies of the sign bit when signed.
+            if byte & 0x80 != 0 {
+                return Err(Leb128Error::Overlong);
+            }
+            let unused = payload >> (remaining - if self.signed { 1 } else { 0 });
+            let all_ones = 0x7f >> (remaining - if self.signed { 1 } else { 0 });
+            if unused != 0 && !(self.signed && unused == all_ones) {
+                return Err(Leb128Error::Overlong);
+            }
+        }
+
+        self.value |= payload << self.shift;
+        self.shift += 7;
+        if byte & 0x80 != 0 {
+            return Ok(None);
+        }
+
+        if self.signed && self.shift < 64 && byte & 0x40 != 0 {
+            self.value |= !0 << self.shift;
+        }
+        Ok(Some(self.value))
+    }
+}
+
+fn decode(data: &[u8], bits: u32, signed: bool) -> Result<(u64, usize), Leb128Error> {
+    let mut decoder = Decoder::new(bits, signed);
+    for (index, byte) in data.iter().enumerate() {
+        if let Some(value) = decoder.push(*byte)? {
+            return Ok((value, index + 1));
+        }
+    }
+
+    Err(Leb128Error::Truncated)
+}
+
+fn read<R: Read>(reader: &mut R, bits: u32, signed: bool) -> io::Result<u64> {
+    let mut decoder = Decoder::new(bits, signed);
+    loop {
+        let mut byte = [0u8];
+        if reader.read(&mut byte)? == 0 {
+            return Err(Leb128Error::Truncated.into());
+        }
+        if let Some(value) = decoder.push(byte[0])? {
+            return Ok(value);
+        }
+    }
+}
+
+fn write_unsigned<W: Write>(writer: &mut W, mut value: u64) -> io::Result<usize> {
+    let mut bytes = Vec::with_capacity(10);
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            bytes.push(byte);
+            break;
+        }
+        bytes.push(byte | 0x80);
+    }
+    writer.write_all(&bytes)?;
+    Ok(bytes.len())
+}
+
+fn write_signed<W: Write>(writer: &mut W, mut value: i64) -> io::Result<usize> {
+    let mut bytes = Vec::with_capacity(10);
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        let done = (value == 0 && byte & 0x40 == 0) || (value == -1 && byte & 0x40 != 0);
+        if done {
+            bytes.push(byte);
+            break;
+        }
+        bytes.push(byte | 0x80);
+    }
+    writer.write_all(&bytes)?;
+    Ok(bytes.len())
+}
+
+pub fn decode_u32(data: &[u8]) -> Result<(u32, usize), Leb128Error> {
+    decode(data, 32, false).map(|(value, length)| (value as u32, length))
+}
+
+pub fn decode_u64(data: &[u8]) -> Result<(u64, usize), Leb128Error> {
+    decode(data, 64, false)
+}
+
+pub fn decode_i32(data: &[u8]) -> Result<(i32, usize), Leb128Error> {
+    decode(data, 32, true).map(|(value, length)| (value as i64 as i32, length))
+}
+
+pub fn decode_i64(data: &[u8]) -> Result<(i64, usize), Leb128Error> {
+    decode(data, 64, true).map(|(value, length)| (value as i64, length))
+}
+
+pub fn read_u32<R: Read>(reader: &mut R) -> io::Result<u32> {
+    read(reader, 32, false).map(|value| value as u32)
+}
+
+pub fn read_u64<R: Read>(reader: &mut R) -> io::Result<u64> {
+    read(reader, 64, false)
+}
+
+pub fn read_i32<R: Read>(reader: &mut R) -> io::Result<i32> {
+    read(reader, 32, true).map(|value| value as i64 as i32)
+}
+
+pub fn read_i64<R: Read>(reader: &mut R) -> io::Result<i64> {
+    read(reader, 64, true).map(|value| value as i64)
+}
+
+/// Writes `value` in as few bytes as possible and returns how many that were.
+pub fn write_u32<W: Write>(writer: &mut W, value: u32) -> io::Result<usize> {
+    write_unsigned(writer, value as u64)
+}
+
+pub fn write_u64<W: Write>(writer: &mut W, value: u64) -> io::Result<usize> {
+    write_unsigned(writer, value)
+}
+
+pub fn write_i32<W: Write>(writer: &mut W, value: i32) -> io::Result<usize> {
+    write_signed(writer, value as i64)
+}
+
+pub fn write_i64<W: Write>(writer: &mut W, value: i64) -> io::Result<usize> {
+    write_signed(writer, value)
+}
+
+/// Encodes `value` into `bytes` bytes, padding with continuation bytes. Sizes that
+/// have to be patched in later are often written this way, e.g. 5 bytes for a u32.
+pub fn write_u32_padded<W: Write>(writer: &mut W, mut value: u32, bytes: usize) -> io::Result<()> {
+    let mut encoded = Vec::with_capacity(bytes);
+    for index in 0..bytes {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        encoded.push(if index + 1 < bytes { byte | 0x80 } else { byte });
+    }
+    if value != 0 || bytes == 0 {
+        return Err(Leb128Error::Overlong.into());
+    }
+    writer.write_all(&encoded)
+}
+
+#[test]
+fn round_trips() -> io::Result<()> {
+    let unsigned = [
+        0,
+        1,
+        63,
+        64,
+        127,
+        128,
+        300,
+        16383,
+        16384,
+        u32::MAX as u64,
+        u64::MAX,
+    ];
+    for value in unsigned.iter().cloned() {
+        let mut bytes = vec![];
+        write_u64(&mut bytes, value)?;
+        assert_eq!(decode_u64(&bytes), Ok((value, bytes.len())));
+        assert_eq!(read_u64(&mut &bytes[..])?, value);
+        if value <= u32::MAX as u64 {
+            assert_eq!(decode_u32(&bytes), Ok((value as u32, bytes.len())));
+        }
+    }
+
+    let signed = [
+        0,
+        1,
+        -1,
+        63,
+        -64,
+        64,
+        -65,
+        8191,
+        -8192,
+        i32::MIN as i64,
+        i32::MAX as i64,
+        i64::MIN,
+        i64::MAX,
+    ];
+    for value in signed.iter().cloned() {
+        let mut bytes = vec![];
+        write_i64(&mut bytes, value)?;
+        assert_eq!(decode_i64(&bytes), Ok((value, bytes.len())));
+        assert_eq!(read_i64(&mut &bytes[..])?, value);
+        if value >= i32::MIN as i64 && value <= i32::MAX as i64 {
+            assert_eq!(decode_i32(&bytes), Ok((value as i32, bytes.len())));
+            assert_eq!(read_i32(&mut &bytes[..])?, value as i32);
+        }
+    }
+
+    Ok(())
+}
+
+#[test]
+fn known_encodings() -> io::Result<()> {
+    let mut bytes = vec![];
+    write_u32(&mut bytes, 624485)?;
+    assert_eq!(bytes, [0xe5, 0x8e, 0x26]);
+
+    let mut bytes = vec![];
+    write_i32(&mut bytes, -123456)?;
+    assert_eq!(bytes, [0xc0, 0xbb, 0x78]);
+
+    let mut bytes = vec![];
+    write_u32_padded(&mut bytes, 3, 5)?;
+    assert_eq!(bytes, [0x83, 0x80, 0x80, 0x80, 0x00]);
+    assert_eq!(decode_u32(&bytes), Ok((3, 5)));
+
+    // Trailing bytes are not part of the number.
+    assert_eq!(decode_u32(&[0x05, 0xff]), Ok((5, 1)));
+
+    Ok(())
+}
+
+#[test]
+fn malformed_numbers_are_rejected() {
+    assert_eq!(decode_u32(&[]), Err(Leb128Error::Truncated));
+    assert_eq!(decode_u32(&[0x80, 0x80]), Err(Leb128Error::Truncated));
+    assert_eq!(
+        decode_u32(&[0x80, 0x80, 0x80, 0x80, 0x80, 0x00]),
+        Err(Leb128Error::Overlong)
+    );
+    // 2^32 does not fit.
+    assert_eq!(
+        decode_u32(&[0x80, 0x80, 0x80, 0x80, 0x10]),
+        Err(Leb128Error::Overlong)
+    );
+    assert_eq!(
+        decode_u32(&[0xff, 0xff, 0xff, 0xff, 0x0f]),
+        Ok((u32::MAX, 5))
+    );
+    assert_eq!(
+        decode_u64(&[0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x02]),
+        Err(Leb128Error::Overlong)
+    );
+    // i32::MIN and one below it.
+    assert_eq!(
+        decode_i32(&[0x80, 0x80, 0x80, 0x80, 0x78]),
+        Ok((i32::MIN, 5))
+    );
+    assert_eq!(
+        decode_i32(&[0xff, 0xff, 0xff, 0xff, 0x77]),
+        Err(Leb128Error::Overlong)
+    );
+    assert_eq!(decode_i64(&[0x80; 11]), Err(Leb128Error::Overlong));
+
+    let error = read_u32(&mut &[0x80u8][..]).unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+    assert!(write_u32_padded(&mut vec![], 300, 1).is_err());
+}
@@ -0,0 +1,910 @@
+//! The `dylink.0` and legacy `dylink` sections of side modules, shared by the runtime,
+//! which links modules, and the registry, which validates them on publish, together with
+//! the bounds-checked `Reader` of the binary format they are parsed with.
+pub mod leb128;
+
+use crate::leb128::Leb128Error;
+use serde_derive::Serialize;
+use std::fmt;
+
+const MAGIC_NUMBER: &[u8; 4] = b"\0asm";
+const VERSION: &[u8; 4] = &[1, 0, 0, 0];
+const CUSTOM_SECTION_ID: u8 = 0;
+const DYLINK_NAME: &str = "dylink";
+const DYLINK_0_NAME: &str = "dylink.0";
+
+// Subsection types of `dylink.0`.
+const WASM_DYLINK_MEM_INFO: u8 = 1;
+const WASM_DYLINK_NEEDED: u8 = 2;
+const WASM_DYLINK_EXPORT_INFO: u8 = 3;
+const WASM_DYLINK_IMPORT_INFO: u8 = 4;
+const WASM_DYLINK_RUNTIME_PATH: u8 = 5;
+
+// Symbol flags of export and import infos.
+pub const WASM_SYMBOL_BINDING_WEAK: u32 = 0x1;
+pub const WASM_SYMBOL_TLS: u32 = 0x100;
+// Largest accepted `mem_align` and `table_align`, a 64 KiB page. Compilers emit
+// far smaller values, and larger ones would waste most of an allocation.
+const MAX_ALIGN_LOG2: u32 = 16;
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DylinkFormat {
+    /// The `dylink` section of older emscripten releases.
+    Legacy,
+    /// The `dylink.0` section of current emscripten and `wasm-ld --shared`.
+    V0,
+}
+
+/// Flags of an exported symbol, from the `dylink.0` export info.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct ExportInfo {
+    pub name: String,
+    pub flags: u32,
+}
+
+/// Flags of an imported symbol, from the `dylink.0` import info.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct ImportInfo {
+    pub module: String,
+    pub field: String,
+    pub flags: u32,
+}
+
+impl ExportInfo {
+    /// The exported global is an offset into the module's thread local storage
+    /// block, which starts at its `__tls_base`, instead of into its data.
+    pub fn is_tls(&self) -> bool {
+        self.flags & WASM_SYMBOL_TLS != 0
+    }
+
+    pub fn is_weak(&self) -> bool {
+        self.flags & WASM_SYMBOL_BINDING_WEAK != 0
+    }
+}
+
+impl ImportInfo {
+    /// The module may be linked even if nothing provides the import.
+    pub fn is_weak(&self) -> bool {
+        self.flags & WASM_SYMBOL_BINDING_WEAK != 0
+    }
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct Dylink {
+    pub format: DylinkFormat,
+    pub mem_size: u32,
+    pub mem_align: u32,
+    pub table_size: u32,
+    pub table_align: u32,
+    pub needed_dynlibs: Vec<String>,
+    // Only `dylink.0` sections carry the following.
+    pub export_info: Vec<ExportInfo>,
+    pub import_info: Vec<ImportInfo>,
+    pub runtime_paths: Vec<String>,
+}
+
+/// Why a binary has no usable `dylink` section. Offsets are relative to the start of the binary.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DylinkError {
+    NotWasm,
+    UnsupportedVersion([u8; 4]),
+    /// The first section is not a custom section, although `dylink` has to come first.
+    NotFirstSection,
+    WrongSectionName(String),
+    Truncated {
+        offset: usize,
+    },
+    /// A LEB128 number at `offset` takes more bytes than its type allows or does not fit it.
+    OverlongLeb128 {
+        offset: usize,
+    },
+    /// `mem_align` or `table_align` is larger than 2^16.
+    AlignmentTooLarge(u32),
+    InvalidUtf8 {
+        offset: usize,
+    },
+    /// Export info, import info and runtime paths can only be written to a `dylink.0` section.
+    NotInLegacyFormat,
+}
+
+impl fmt::Display for DylinkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DylinkError::NotWasm => write!(f, "not a WebAssembly binary"),
+            DylinkError::UnsupportedVersion(version) => {
+                write!(f, "unsupported WebAssembly version {:?}", version)
+            }
+            DylinkError::NotFirstSection => write!(f, "dylink section needs to be first"),
+            DylinkError::WrongSectionName(name) => write!(
+                f,
+                "first section is `{}` instead of `{}` or `{}`",
+                name, DYLINK_0_NAME, DYLINK_NAME
+            ),
+            DylinkError::Truncated { offset } => {
+                write!(f, "data is truncated at offset {}", offset)
+            }
+            DylinkError::OverlongLeb128 { offset } => {
+                write!(
+                    f,
+                    "LEB128 number at offset {} is overlong or out of range",
+                    offset
+                )
+            }
+            DylinkError::AlignmentTooLarge(align) => {
+                write!(
+                    f,
+                    "alignment 2^{} is larger than 2^{}",
+                    align, MAX_ALIGN_LOG2
+                )
+            }
+            DylinkError::InvalidUtf8 { offset } => {
+                write!(f, "string at offset {} is not valid UTF-8", offset)
+            }
+            DylinkError::NotInLegacyFormat => write!(
+                f,
+                "a legacy `{}` section cannot hold export info, import info or runtime paths",
+                DYLINK_NAME
+            ),
+        }
+    }
+}
+
+impl std::error::Error for DylinkError {}
+
+/// Bounds-checked reader of the WebAssembly binary format, over a section or subsection
+/// that ends at `end`, which may lie before the end of the binary.
+pub struct Reader<'a> {
+    binary: &'a [u8],
+    position: usize,
+    end: usize,
+}
+
+// One of the slice decoders of `leb128`.
+type Decode<T> = fn(&[u8]) -> Result<(T, usize), Leb128Error>;
+
+impl<'a> Reader<'a> {
+    /// Reader of all of `data`. Offsets in errors are relative to its start.
+    pub fn new(data: &'a [u8]) -> Self {
+        Reader {
+            binary: data,
+            position: 0,
+            end: data.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.position >= self.end
+    }
+
+    /// The bytes that are left to read.
+    pub fn remaining(&self) -> &'a [u8] {
+        &self.binary[self.position.min(self.end)..self.end]
+    }
+
+    pub fn byte(&mut self) -> Result<u8, DylinkError> {
+        if self.position >= self.end {
+            return Err(DylinkError::Truncated {
+                offset: self.position,
+            });
+        }
+        let byte = self.binary[self.position];
+        self.position += 1;
+        Ok(byte)
+    }
+
+    pub fn bytes(&mut self, length: usize) -> Result<&'a [u8], DylinkError> {
+        let start = self.position;
+        let end = start
+            .checked_add(length)
+            .filter(|end| *end <= self.end)
+            .ok_or(DylinkError::Truncated { offset: self.end })?;
+        self.position = end;
+        Ok(&self.binary[start..end])
+    }
+
+    fn decode<T>(&mut self, decode: Decode<T>) -> Result<T, DylinkError> {
+        let start = self.position;
+        let (value, length) = decode(self.remaining()).map_err(|e| match e {
+            Leb128Error::Truncated => DylinkError::Truncated { offset: self.end },
+            Leb128Error::Overlong => DylinkError::OverlongLeb128 { offset: start },
+        })?;
+        self.position += length;
+        Ok(value)
+    }
+
+    /// An unsigned 32-bit LEB128 number, such as a count, index or length.
+    pub fn leb128(&mut self) -> Result<u32, DylinkError> {
+        self.decode(leb128::decode_u32)
+    }
+
+    pub fn leb128_u64(&mut self) -> Result<u64, DylinkError> {
+        self.decode(leb128::decode_u64)
+    }
+
+    pub fn leb128_i32(&mut self) -> Result<i32, DylinkError> {
+        self.decode(leb128::decode_i32)
+    }
+
+    pub fn leb128_i64(&mut self) -> Result<i64, DylinkError> {
+        self.decode(leb128::decode_i64)
+    }
+
+    /// A string prefixed with its length in bytes.
+    pub fn string(&mut self) -> Result<&'a str, DylinkError> {
+        let length = self.leb128()? as usize;
+        let start = self.position;
+        std::str::from_utf8(self.bytes(length)?)
+            .map_err(|_| DylinkError::InvalidUtf8 { offset: start })
+    }
+
+    /// A vector of strings prefixed with its length.
+    pub fn strings(&mut self) -> Result<Vec<String>, DylinkError> {
+        let count = self.leb128()?;
+        let mut strings = Vec::new();
+        for _ in 0..count {
+            strings.push(self.string()?.to_string());
+        }
+        Ok(strings)
+    }
+
+    /// Reader of the next `length` bytes, which the current reader skips.
+    pub fn subsection(&mut self, length: usize) -> Result<Reader<'a>, DylinkError> {
+        let start = self.position;
+        self.bytes(length)?;
+        Ok(Reader {
+            binary: self.binary,
+            position: start,
+            end: self.position,
+        })
+    }
+}
+
+/// Checks the magic number and version that start every binary.
+fn check_header(binary: &[u8]) -> Result<(), DylinkError> {
+    if binary.len() < MAGIC_NUMBER.len() || &binary[..4] != MAGIC_NUMBER {
+        return Err(DylinkError::NotWasm);
+    }
+    match binary.get(4..8) {
+        Some(version) if version == VERSION => {}
+        Some(version) => {
+            return Err(DylinkError::UnsupportedVersion([
+                version[0], version[1], version[2], version[3],
+            ]))
+        }
+        None => {
+            return Err(DylinkError::Truncated {
+                offset: binary.len(),
+            })
+        }
+    }
+
+    Ok(())
+}
+
+/// Offset just past the `dylink.0` or `dylink` section at the start of `binary`,
+/// or the offset right after the header if the binary has none.
+fn dylink_section_end(binary: &[u8]) -> Result<usize, DylinkError> {
+    check_header(binary)?;
+    let mut reader = Reader {
+        binary,
+        position: 8,
+        end: binary.len(),
+    };
+    if reader.position == reader.end || reader.byte()? != CUSTOM_SECTION_ID {
+        return Ok(8);
+    }
+    let section_size = reader.leb128()? as usize;
+    reader.end = reader
+        .position
+        .checked_add(section_size)
+        .filter(|end| *end <= binary.len())
+        .ok_or(DylinkError::Truncated {
+            offset: binary.len(),
+        })?;
+
+    let name = reader.string()?;
+    if name == DYLINK_NAME || name == DYLINK_0_NAME {
+        Ok(reader.end)
+    } else {
+        Ok(8)
+    }
+}
+
+// Writing to a `Vec` cannot fail.
+fn write_u32(output: &mut Vec<u8>, value: u32) {
+    leb128::write_u32(output, value).expect("writing to a Vec");
+}
+
+fn write_string(output: &mut Vec<u8>, string: &str) {
+    write_u32(output, string.len() as u32);
+    output.extend_from_slice(string.as_bytes());
+}
+
+fn write_strings(output: &mut Vec<u8>, strings: &[String]) {
+    write_u32(output, strings.len() as u32);
+    for string in strings {
+        write_string(output, string);
+    }
+}
+
+fn write_subsection(output: &mut Vec<u8>, kind: u8, contents: &[u8]) {
+    output.push(kind);
+    write_u32(output, contents.len() as u32);
+    output.extend_from_slice(contents);
+}
+
+/// Rounds `value` up to a multiple of `alignment`, which has to be a power of two.
+/// Returns `None` if the result does not fit 32 bits.
+pub fn align_up(value: u32, alignment: u32) -> Option<u32> {
+    debug_assert!(alignment.is_power_of_two());
+    value
+        .checked_add(alignment - 1)
+        .map(|value| value & !(alignment - 1))
+}
+
+impl Dylink {
+    /// An empty section, to be filled in and written with `write_dylink`.
+    pub fn new(format: DylinkFormat) -> Self {
+        Dylink {
+            format,
+            mem_size: 0,
+            mem_align: 0,
+            table_size: 0,
+            table_align: 0,
+            needed_dynlibs: vec![],
+            export_info: vec![],
+            import_info: vec![],
+            runtime_paths: vec![],
+        }
+    }
+
+    /// Parses the `dylink.0` or legacy `dylink` section, which has to be the first section of `binary`.
+    /// Never panics, whatever the input.
+    pub fn check_dylink(binary: &[u8]) -> Result<Self, DylinkError> {
+        check_header(binary)?;
+        let mut reader = Reader {
+            binary,
+            position: 8,
+            end: binary.len(),
+        };
+        if reader.byte()? != CUSTOM_SECTION_ID {
+            return Err(DylinkError::NotFirstSection);
+        }
+        let section_size = reader.leb128()? as usize;
+        reader.end = reader
+            .position
+            .checked_add(section_size)
+            .filter(|end| *end <= binary.len())
+            .ok_or(DylinkError::Truncated {
+                offset: binary.len(),
+            })?;
+
+        let name_length = reader.leb128()? as usize;
+        let name = reader.bytes(name_length)?;
+        let dylink = if name == DYLINK_NAME.as_bytes() {
+            Dylink::parse_legacy(&mut reader)?
+        } else if name == DYLINK_0_NAME.as_bytes() {
+            Dylink::parse_v0(&mut reader)?
+        } else {
+            return Err(DylinkError::WrongSectionName(
+                String::from_utf8_lossy(name).to_string(),
+            ));
+        };
+
+        for align in &[dylink.mem_align, dylink.table_align] {
+            if *align > MAX_ALIGN_LOG2 {
+                return Err(DylinkError::AlignmentTooLarge(*align));
+            }
+        }
+
+        Ok(dylink)
+    }
+
+    /// Alignment of the module's data in bytes.
+    pub fn memory_alignment(&self) -> u32 {
+        1 << self.mem_align
+    }
+
+    /// Alignment of the module's first table slot.
+    pub fn table_alignment(&self) -> u32 {
+        1 << self.table_align
+    }
+
+    /// Size of an allocation that can hold the module's data at an aligned
+    /// address, wherever the allocator places it.
+    pub fn memory_allocation_size(&self) -> Option<u32> {
+        self.mem_size.checked_add(self.memory_alignment() - 1)
+    }
+
+    /// `__memory_base` for data placed in an allocation starting at `address`.
+    pub fn memory_base(&self, address: u32) -> Option<u32> {
+        align_up(address, self.memory_alignment())
+    }
+
+    /// How many slots to grow a table of `table_size` slots by, and the aligned
+    /// `__table_base` of the module's functions among the new slots.
+    pub fn table_placement(&self, table_size: u32) -> Option<(u32, u32)> {
+        let table_base = align_up(table_size, self.table_alignment())?;
+        let grow_by = (table_base - table_size).checked_add(self.table_size)?;
+        Some((grow_by, table_base))
+    }
+
+    /// Encodes the whole custom section, as `dylink.0` or legacy `dylink` depending on `format`.
+    pub fn encode(&self) -> Result<Vec<u8>, DylinkError> {
+        for align in &[self.mem_align, self.table_align] {
+            if *align > MAX_ALIGN_LOG2 {
+                return Err(DylinkError::AlignmentTooLarge(*align));
+            }
+        }
+
+        let mut mem_info = vec![];
+        for value in &[
+            self.mem_size,
+            self.mem_align,
+            self.table_size,
+            self.table_align,
+        ] {
+            write_u32(&mut mem_info, *value);
+        }
+
+        let mut contents = vec![];
+        match self.format {
+            DylinkFormat::Legacy => {
+                if !self.export_info.is_empty()
+                    || !self.import_info.is_empty()
+                    || !self.runtime_paths.is_empty()
+                {
+                    return Err(DylinkError::NotInLegacyFormat);
+                }
+                write_string(&mut contents, DYLINK_NAME);
+                contents.extend_from_slice(&mem_info);
+                write_strings(&mut contents, &self.needed_dynlibs);
+            }
+            DylinkFormat::V0 => {
+                write_string(&mut contents, DYLINK_0_NAME);
+                write_subsection(&mut contents, WASM_DYLINK_MEM_INFO, &mem_info);
+                if !self.needed_dynlibs.is_empty() {
+                    let mut needed = vec![];
+                    write_strings(&mut needed, &self.needed_dynlibs);
+                    write_subsection(&mut contents, WASM_DYLINK_NEEDED, &needed);
+                }
+                if !self.export_info.is_empty() {
+                    let mut exports = vec![];
+                    write_u32(&mut exports, self.export_info.len() as u32);
+                    for export in &self.export_info {
+                        write_string(&mut exports, &export.name);
+                        write_u32(&mut exports, export.flags);
+                    }
+                    write_subsection(&mut contents, WASM_DYLINK_EXPORT_INFO, &exports);
+                }
+                if !self.import_info.is_empty() {
+                    let mut imports = vec![];
+                    write_u32(&mut imports, self.import_info.len() as u32);
+                    for import in &self.import_info {
+                        write_string(&mut imports, &import.module);
+                        write_string(&mut imports, &import.field);
+                        write_u32(&mut imports, import.flags);
+                    }
+                    write_subsection(&mut contents, WASM_DYLINK_IMPORT_INFO, &imports);
+                }
+                if !self.runtime_paths.is_empty() {
+                    let mut paths = vec![];
+                    write_strings(&mut paths, &self.runtime_paths);
+                    write_subsection(&mut contents, WASM_DYLINK_RUNTIME_PATH, &paths);
+                }
+            }
+        }
+
+        let mut section = vec![CUSTOM_SECTION_ID];
+        write_u32(&mut section, contents.len() as u32);
+        section.extend_from_slice(&contents);
+        Ok(section)
+    }
+
+    /// Returns `binary` with its dylink section replaced by this one, or with this one
+    /// inserted as the first section if it has none. All other sections are copied unchanged,
+    /// so converting a binary is `check_dylink`, changing `format` and `write_dylink`.
+    pub fn write_dylink(&self, binary: &[u8]) -> Result<Vec<u8>, DylinkError> {
+        let section = self.encode()?;
+        let end = dylink_section_end(binary)?;
+
+        let mut output = Vec::with_capacity(binary.len() - end + 8 + section.len());
+        output.extend_from_slice(&binary[..8]);
+        output.extend_from_slice(&section);
+        output.extend_from_slice(&binary[end..]);
+        Ok(output)
+    }
+
+    fn parse_legacy(reader: &mut Reader) -> Result<Self, DylinkError> {
+        let mut dylink = Dylink::new(DylinkFormat::Legacy);
+        dylink.mem_size = reader.leb128()?;
+        dylink.mem_align = reader.leb128()?;
+        dylink.table_size = reader.leb128()?;
+        dylink.table_align = reader.leb128()?;
+        dylink.needed_dynlibs = reader.strings()?;
+
+        Ok(dylink)
+    }
+
+    fn parse_v0(reader: &mut Reader) -> Result<Self, DylinkError> {
+        let mut dylink = Dylink::new(DylinkFormat::V0);
+        while !reader.is_empty() {
+            let kind = reader.byte()?;
+            let length = reader.leb128()? as usize;
+            let mut subsection = reader.subsection(length)?;
+            match kind {
+                WASM_DYLINK_MEM_INFO => {
+                    dylink.mem_size = subsection.leb128()?;
+                    dylink.mem_align = subsection.leb128()?;
+                    dylink.table_size = subsection.leb128()?;
+                    dylink.table_align = subsection.leb128()?;
+                }
+                WASM_DYLINK_NEEDED => dylink.needed_dynlibs = subsection.strings()?,
+                WASM_DYLINK_EXPORT_INFO => {
+                    for _ in 0..subsection.leb128()? {
+                        let name = subsection.string()?.to_string();
+                        let flags = subsection.leb128()?;
+                        dylink.export_info.push(ExportInfo { name, flags });
+                    }
+                }
+                WASM_DYLINK_IMPORT_INFO => {
+                    for _ in 0..subsection.leb128()? {
+                        let module = subsection.string()?.to_string();
+                        let field = subsection.string()?.to_string();
+                        let flags = subsection.leb128()?;
+                        dylink.import_info.push(ImportInfo {
+                            module,
+                            field,
+                            flags,
+                        });
+                    }
+                }
+                WASM_DYLINK_RUNTIME_PATH => dylink.runtime_paths = subsection.strings()?,
+                // Newer subsections can be ignored, their size is known.
+                _ => {}
+            }
+        }
+
+        Ok(dylink)
+    }
+
+    /// Names of the exports that are offsets into thread local storage.
+    pub fn tls_exports(&self) -> impl Iterator<Item = &str> {
+        self.export_info
+            .iter()
+            .filter(|export| export.is_tls())
+            .map(|export| export.name.as_str())
+    }
+
+    pub fn is_tls_export(&self, name: &str) -> bool {
+        self.tls_exports().any(|export| export == name)
+    }
+
+    pub fn is_weak_export(&self, name: &str) -> bool {
+        self.export_info
+            .iter()
+            .any(|export| export.name == name && export.is_weak())
+    }
+
+    /// (module, field) of the imports that may stay unresolved.
+    pub fn weak_imports(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.import_info
+            .iter()
+            .filter(|import| import.is_weak())
+            .map(|import| (import.module.as_str(), import.field.as_str()))
+    }
+
+    pub fn is_weak_import(&self, module: &str, field: &str) -> bool {
+        self.weak_imports().any(|import| import == (module, field))
+    }
+}
+
+// wasm-objdump result of "side.wasm";
+// Section Details:
+// Custom:
+//  - name: "dylink"
+//  - mem_size     : 5243072
+//  - mem_p2align  : 4
+//  - table_size   : 0
+//  - table_p2align: 0
+
+#[test]
+fn check_with_no_dynamic_libraries() -> Result<(), DylinkError> {
+    let dylink = Dylink::check_dylink(&[
+        0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, 0x00, 0x0f, 0x06, 0x64, 0x79, 0x6c, 0x69,
+        0x6e, 0x6b, 0xc0, 0x81, 0xc0, 0x02, 0x04, 0x00, 0x00, 0x00,
+    ])?;
+
+    assert_eq!(dylink.mem_size, 5243072);
+    assert_eq!(dylink.mem_align, 4);
+    assert_eq!(dylink.table_size, 0);
+    assert_eq!(dylink.table_align, 0);
+
+    Ok(())
+}
+
+// wasm-objdump result of "dynamic_linking_example2.wasm":
+// Section Details:
+// Custom:
+//  - name: "dylink"
+//  - mem_size     : 30
+//  - mem_p2align  : 0
+//  - table_size   : 0
+//  - table_p2align: 0
+//  - needed_dynlibs[1]:
+//   - dynamic_linking_example_side.wasm
+
+#[test]
+fn check_with_dynamic_library() -> Result<(), DylinkError> {
+    let dylink = Dylink::check_dylink(&[
+        0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, 0x00, 0x2e, 0x06, 0x64, 0x79, 0x6c, 0x69,
+        0x6e, 0x6b, 0x1e, 0x00, 0x00, 0x00, 0x01, 0x21, 0x64, 0x79, 0x6e, 0x61, 0x6d, 0x69, 0x63,
+        0x5f, 0x6c, 0x69, 0x6e, 0x6b, 0x69, 0x6e, 0x67, 0x5f, 0x65, 0x78, 0x61, 0x6d, 0x70, 0x6c,
+        0x65, 0x5f, 0x73, 0x69, 0x64, 0x65, 0x2e, 0x77, 0x61, 0x73, 0x6d,
+    ])?;
+
+    assert_eq!(dylink.mem_size, 30);
+    assert_eq!(dylink.mem_align, 0);
+    assert_eq!(dylink.table_size, 0);
+    assert_eq!(dylink.table_align, 0);
+    assert_eq!(
+        dylink.needed_dynlibs[0],
+        "dynamic_linking_example_side.wasm"
+    );
+
+    Ok(())
+}
+
+// wasm-objdump result of "dynamic_linking_example_multiple_dependencies.wasm",
+// with the section size covering all four names:
+// Section Details:
+// Custom:
+//  - name: "dylink"
+//  - mem_size     : 30
+//  - mem_p2align  : 0
+//  - table_size   : 0
+//  - table_p2align: 0
+//  - needed_dynlibs[4]:
+//   - dynamic_linking_example_side.wasm
+//   - dynamic_linking_example.wasm
+//   - main.wasm
+//   - bar.wasm
+
+#[test]
+fn check_with_dynamic_libraries() -> Result<(), DylinkError> {
+    let dylink = Dylink::check_dylink(&[
+        0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, 0x00, 0x5e, 0x06, 0x64, 0x79, 0x6c, 0x69,
+        0x6e, 0x6b, 0x1e, 0x00, 0x00, 0x00, 0x04, 0x21, 0x64, 0x79, 0x6e, 0x61, 0x6d, 0x69, 0x63,
+        0x5f, 0x6c, 0x69, 0x6e, 0x6b, 0x69, 0x6e, 0x67, 0x5f, 0x65, 0x78, 0x61, 0x6d, 0x70, 0x6c,
+        0x65, 0x5f, 0x73, 0x69, 0x64, 0x65, 0x2e, 0x77, 0x61, 0x73, 0x6d, 0x1c, 0x64, 0x79, 0x6e,
+        0x61, 0x6d, 0x69, 0x63, 0x5f, 0x6c, 0x69, 0x6e, 0x6b, 0x69, 0x6e, 0x67, 0x5f, 0x65, 0x78,
+        0x61, 0x6d, 0x70, 0x6c, 0x65, 0x2e, 0x77, 0x61, 0x73, 0x6d, 0x09, 0x6d, 0x61, 0x69, 0x6e,
+        0x2e, 0x77, 0x61, 0x73, 0x6d, 0x08, 0x62, 0x61, 0x72, 0x2e, 0x77, 0x61, 0x73, 0x6d,
+    ])?;
+
+    assert_eq!(dylink.mem_size, 30);
+    assert_eq!(dylink.mem_align, 0);
+    assert_eq!(dylink.table_size, 0);
+    assert_eq!(dylink.table_align, 0);
+    assert_eq!(
+        dylink.needed_dynlibs[0],
+        "dynamic_linking_example_side.wasm"
+    );
+    assert_eq!(dylink.needed_dynlibs[1], "dynamic_linking_example.wasm");
+    assert_eq!(dylink.needed_dynlibs[2], "main.wasm");
+    assert_eq!(dylink.needed_dynlibs[3], "bar.wasm");
+
+    Ok(())
+}
+
+#[test]
+fn malformed_sections_are_errors() {
+    let valid = [
+        0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, 0x00, 0x0f, 0x06, 0x64, 0x79, 0x6c, 0x69,
+        0x6e, 0x6b, 0xc0, 0x81, 0xc0, 0x02, 0x04, 0x00, 0x00, 0x00,
+    ];
+    for length in 0..valid.len() {
+        assert!(Dylink::check_dylink(&valid[..length]).is_err());
+    }
+
+    let mut binary = valid.to_vec();
+    binary[0] = b'X';
+    assert_eq!(
+        Dylink::check_dylink(&binary).unwrap_err(),
+        DylinkError::NotWasm
+    );
+
+    let mut binary = valid.to_vec();
+    binary[8] = 1;
+    assert_eq!(
+        Dylink::check_dylink(&binary).unwrap_err(),
+        DylinkError::NotFirstSection
+    );
+
+    let mut binary = valid.to_vec();
+    binary[11] = b'D';
+    assert_eq!(
+        Dylink::check_dylink(&binary).unwrap_err(),
+        DylinkError::WrongSectionName("Dylink".to_string())
+    );
+
+    // mem_size encoded in 6 bytes.
+    let mut binary = valid[..17].to_vec();
+    binary.extend_from_slice(&[0x80, 0x80, 0x80, 0x80, 0x80, 0x00, 0x04, 0x00, 0x00, 0x00]);
+    binary[9] = (binary.len() - 10) as u8;
+    assert_eq!(
+        Dylink::check_dylink(&binary).unwrap_err(),
+        DylinkError::OverlongLeb128 { offset: 17 }
+    );
+
+    // One needed library with a name that is not UTF-8.
+    let mut binary = valid[..17].to_vec();
+    binary.extend_from_slice(&[0x00, 0x00, 0x00, 0x00, 0x01, 0x02, 0xff, 0xfe]);
+    binary[9] = (binary.len() - 10) as u8;
+    assert_eq!(
+        Dylink::check_dylink(&binary).unwrap_err(),
+        DylinkError::InvalidUtf8 { offset: 23 }
+    );
+
+    // A section size reaching past the end of the binary.
+    let mut binary = valid.to_vec();
+    binary[9] = 0x7f;
+    assert!(matches!(
+        Dylink::check_dylink(&binary),
+        Err(DylinkError::Truncated { .. })
+    ));
+}
+
+// A `dylink.0` section as emitted by `wasm-ld --shared`, with every subsection type
+// and an unknown subsection 0x7f that has to be skipped.
+#[test]
+fn check_dylink_0() -> Result<(), DylinkError> {
+    let mut section = vec![0x08];
+    section.extend_from_slice(b"dylink.0");
+    // mem info
+    section.extend_from_slice(&[0x01, 0x05, 0x90, 0x03, 0x02, 0x01, 0x00]);
+    // needed: libfoo.so
+    section.extend_from_slice(&[0x02, 0x0b, 0x01, 0x09]);
+    section.extend_from_slice(b"libfoo.so");
+    // export info: counter is TLS, helper is weak
+    section.extend_from_slice(&[0x03, 0x13, 0x02, 0x07]);
+    section.extend_from_slice(b"counter");
+    section.extend_from_slice(&[0x80, 0x02, 0x06]);
+    section.extend_from_slice(b"helper");
+    section.push(0x01);
+    // import info: env.optional is weak
+    section.extend_from_slice(&[0x04, 0x0f, 0x01, 0x03]);
+    section.extend_from_slice(b"env");
+    section.push(0x08);
+    section.extend_from_slice(b"optional");
+    section.push(0x01);
+    // runtime path: $ORIGIN
+    section.extend_from_slice(&[0x05, 0x09, 0x01, 0x07]);
+    section.extend_from_slice(b"$ORIGIN");
+    section.extend_from_slice(&[0x7f, 0x02, 0xaa, 0xbb]);
+
+    let mut binary = vec![
+        0x00,
+        0x61,
+        0x73,
+        0x6d,
+        0x01,
+        0x00,
+        0x00,
+        0x00,
+        0x00,
+        section.len() as u8,
+    ];
+    binary.extend_from_slice(&section);
+    let dylink = Dylink::check_dylink(&binary)?;
+
+    assert_eq!(dylink.format, DylinkFormat::V0);
+    assert_eq!(dylink.mem_size, 400);
+    assert_eq!(dylink.mem_align, 2);
+    assert_eq!(dylink.table_size, 1);
+    assert_eq!(dylink.needed_dynlibs, vec!["libfoo.so"]);
+    assert_eq!(dylink.tls_exports().collect::<Vec<_>>(), vec!["counter"]);
+    assert!(dylink.is_tls_export("counter"));
+    assert!(!dylink.is_tls_export("helper"));
+    assert!(dylink.export_info[1].is_weak());
+    assert!(dylink.is_weak_import("env", "optional"));
+    assert_eq!(dylink.runtime_paths, vec!["$ORIGIN"]);
+
+    // A subsection claiming more bytes than the section has.
+    let last = binary.len() - 3;
+    binary[last] = 0x03;
+    assert!(Dylink::check_dylink(&binary).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn placement_honours_alignment() -> Result<(), DylinkError> {
+    // mem_size 100 aligned to 2^12, table_size 3 aligned to 2^4.
+    let dylink = Dylink::check_dylink(&[
+        0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, 0x00, 0x0c, 0x06, 0x64, 0x79, 0x6c, 0x69,
+        0x6e, 0x6b, 0x64, 0x0c, 0x03, 0x04, 0x00,
+    ])?;
+    assert_eq!(dylink.memory_alignment(), 4096);
+    assert_eq!(dylink.table_alignment(), 16);
+
+    let size = dylink.memory_allocation_size().unwrap();
+    for address in &[8, 4096, 4100, 70000] {
+        let base = dylink.memory_base(*address).unwrap();
+        assert_eq!(base % 4096, 0);
+        assert!(base >= *address && base + dylink.mem_size <= *address + size);
+    }
+
+    assert_eq!(dylink.table_placement(0), Some((3, 0)));
+    assert_eq!(dylink.table_placement(17), Some((18, 32)));
+    assert_eq!(dylink.table_placement(32), Some((3, 32)));
+
+    assert_eq!(align_up(5, 1), Some(5));
+    assert_eq!(align_up(u32::MAX, 16), None);
+
+    Ok(())
+}
+
+#[test]
+fn huge_alignments_are_rejected() {
+    let binary = [
+        0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, 0x00, 0x0c, 0x06, 0x64, 0x79, 0x6c, 0x69,
+        0x6e, 0x6b, 0x64, 0x28, 0x03, 0x04, 0x00,
+    ];
+    assert_eq!(
+        Dylink::check_dylink(&binary).unwrap_err(),
+        DylinkError::AlignmentTooLarge(40)
+    );
+}
+
+#[test]
+fn written_sections_round_trip() -> Result<(), DylinkError> {
+    // The legacy section of "side.wasm" is written back byte for byte.
+    let side = [
+        0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, 0x00, 0x0f, 0x06, 0x64, 0x79, 0x6c, 0x69,
+        0x6e, 0x6b, 0xc0, 0x81, 0xc0, 0x02, 0x04, 0x00, 0x00, 0x00,
+    ];
+    let dylink = Dylink::check_dylink(&side)?;
+    assert_eq!(dylink.write_dylink(&side)?, side);
+
+    // Converting to `dylink.0` and back keeps the type section that follows.
+    let mut binary = side.to_vec();
+    binary.extend_from_slice(&[0x01, 0x04, 0x01, 0x60, 0x00, 0x00]);
+    let mut converted = dylink.clone();
+    converted.format = DylinkFormat::V0;
+    converted.needed_dynlibs.push("libfoo.so".to_string());
+    converted.export_info.push(ExportInfo {
+        name: "counter".to_string(),
+        flags: WASM_SYMBOL_TLS,
+    });
+    let v0 = converted.write_dylink(&binary)?;
+    assert_eq!(Dylink::check_dylink(&v0)?, converted);
+    assert!(v0.ends_with(&[0x01, 0x04, 0x01, 0x60, 0x00, 0x00]));
+
+    converted.format = DylinkFormat::Legacy;
+    assert_eq!(
+        converted.write_dylink(&v0).unwrap_err(),
+        DylinkError::NotInLegacyFormat
+    );
+    converted.export_info.clear();
+    let legacy = converted.write_dylink(&v0)?;
+    assert_eq!(Dylink::check_dylink(&legacy)?, converted);
+    assert_eq!(legacy[legacy.len() - 6..], binary[side.len()..]);
+
+    // A binary without a dylink section gets one in front of its first section.
+    let plain = [
+        0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, 0x01, 0x04, 0x01, 0x60, 0x00, 0x00,
+    ];
+    let emitted = Dylink::new(DylinkFormat::V0).write_dylink(&plain)?;
+    assert_eq!(
+        Dylink::check_dylink(&emitted)?,
+        Dylink::new(DylinkFormat::V0)
+    );
+    assert!(emitted.ends_with(&plain[8..]));
+    assert_eq!(
+        Dylink::new(DylinkFormat::V0).write_dylink(b"\0asm"),
+        Err(DylinkError::Truncated { offset: 4 })
+    );
+
+    Ok(())
+}
@@ -0,0 +1,358 @@
+use crate::compression::Encoding;
+use anyhow::{anyhow, Result};
+use serde_derive::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, SystemTime};
+
+const DIGEST_ALGORITHM: &str = "sha256";
+const INDEX_FILE: &str = "index.json";
+const BLOB_DIRECTORY: &str = "blobs";
+const TEMPORARY_MARKER: &str = ".tmp-";
+const COMPRESSED_EXTENSION: &str = "zst";
+// Temporary files younger than this may belong to a write that is still going on, in
+// another process or in another `Manager` of this one, which has the same pid.
+const PARTIAL_FILE_AGE: Duration = Duration::from_secs(10 * 60);
+
+static TEMPORARY_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Identifies one build of a module: the same id and version served
+/// by two different sources are kept apart.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct CacheKey {
+    pub id: String,
+    pub version: Option<String>,
+    pub source: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct CacheEntry {
+    #[serde(flatten)]
+    key: CacheKey,
+    digest: String,
+}
+
+/// Content-addressed module store.
+///
+/// Binaries are stored zstd compressed, once per digest of the uncompressed binary, in
+/// `<root>/blobs/sha256/<hex>.zst`, and `<root>/index.json` maps every (id, version, source)
+/// to the digest of its binary.
+pub struct Cache {
+    root: PathBuf,
+    entries: Vec<CacheEntry>,
+}
+
+pub fn digest(binary: &[u8]) -> String {
+    format!(
+        "{}:{}",
+        DIGEST_ALGORITHM,
+        hex::encode(Sha256::digest(binary))
+    )
+}
+
+/// Writes `contents` to a temporary file next to `path`, flushes it to disk and
+/// renames it over `path`, so a reader sees either the old or the complete new file.
+fn write_atomically(path: &Path, contents: &[u8]) -> Result<()> {
+    let directory = path
+        .parent()
+        .ok_or(anyhow!("{} has no parent directory", path.display()))?;
+    let filename = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .ok_or(anyhow!("{} has no file name", path.display()))?;
+    let temporary = directory.join(format!(
+        "{}{}{}-{}",
+        filename,
+        TEMPORARY_MARKER,
+        std::process::id(),
+        TEMPORARY_COUNTER.fetch_add(1, Ordering::SeqCst)
+    ));
+
+    let result = (|| -> Result<()> {
+        let mut file = fs::File::create(&temporary)?;
+        file.write_all(contents)?;
+        file.sync_all()?;
+        fs::rename(&temporary, path)?;
+        Ok(())
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&temporary);
+    }
+    result?;
+
+    // Persist the rename itself. Directories cannot be opened for syncing on every
+    // platform, which only weakens durability and is therefore not an error.
+    if let Ok(directory) = fs::File::open(directory) {
+        let _ = directory.sync_all();
+    }
+
+    Ok(())
+}
+
+/// Deletes temporary files left behind by writes that were interrupted,
+/// for example because the process was killed, once they are `PARTIAL_FILE_AGE` old.
+fn remove_partial_files(directory: &Path) -> Result<()> {
+    if !directory.exists() {
+        return Ok(());
+    }
+
+    for entry in fs::read_dir(directory)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            remove_partial_files(&path)?;
+        } else if path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .map(|name| name.contains(TEMPORARY_MARKER))
+            .unwrap_or(false)
+            && is_abandoned(&path)
+        {
+            warn!("Removing partially written cache file {}", path.display());
+            fs::remove_file(&path)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn is_abandoned(path: &Path) -> bool {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .and_then(|modified| SystemTime::now().duration_since(modified).ok())
+        .map(|age| age >= PARTIAL_FILE_AGE)
+        .unwrap_or(false)
+}
+
+impl Cache {
+    pub fn open(root: &str) -> Result<Self> {
+        let root = PathBuf::from(root);
+        remove_partial_files(&root)?;
+
+        let index = root.join(INDEX_FILE);
+        let entries: Vec<CacheEntry> = if index.exists() {
+            serde_json::from_slice(&fs::read(&index)?).unwrap_or_else(|e| {
+                warn!("Ignoring unreadable cache index {}: {}", index.display(), e);
+                vec![]
+            })
+        } else {
+            vec![]
+        };
+
+        let mut cache = Self { root, entries };
+        let count = cache.entries.len();
+        let entries = std::mem::take(&mut cache.entries);
+        cache.entries = entries
+            .into_iter()
+            .filter(|entry| cache.contains(&entry.digest))
+            .collect();
+        if cache.entries.len() != count {
+            cache.write_index()?;
+        }
+
+        Ok(cache)
+    }
+
+    fn blob_path(&self, digest: &str) -> Result<PathBuf> {
+        let hex = digest
+            .strip_prefix(&format!("{}:", DIGEST_ALGORITHM))
+            .filter(|hex| !hex.is_empty() && hex.chars().all(|c| c.is_ascii_hexdigit()))
+            .ok_or(anyhow!("Unsupported digest `{}`", digest))?;
+
+        Ok(self
+            .root
+            .join(BLOB_DIRECTORY)
+            .join(DIGEST_ALGORITHM)
+            .join(format!("{}.{}", hex, COMPRESSED_EXTENSION)))
+    }
+
+    pub fn lookup(&self, key: &CacheKey) -> Option<&str> {
+        self.entries
+            .iter()
+            .find(|entry| &entry.key == key)
+            .map(|entry| entry.digest.as_str())
+    }
+
+    pub fn contains(&self, digest: &str) -> bool {
+        self.stored_size(digest).is_some()
+    }
+
+    /// Size of the blob with `digest` as stored on disk.
+    pub fn stored_size(&self, digest: &str) -> Option<u64> {
+        fs::metadata(self.blob_path(digest).ok()?)
+            .map(|metadata| metadata.len())
+            .ok()
+    }
+
+    /// Reads and decompresses the blob with `digest`, making sure its content
+    /// still matches the digest.
+    pub fn read(&self, digest: &str) -> Result<Vec<u8>> {
+        let binary = Encoding::Zstd.decode(&fs::read(self.blob_path(digest)?)?)?;
+        let actual = self::digest(&binary);
+        if actual != digest {
+            return Err(anyhow!(
+                "Cached blob {} is corrupted, its content hashes to {}",
+                digest,
+                actual
+            ));
+        }
+
+        Ok(binary)
+    }
+
+    /// Stores `binary` under its digest, unless an identical binary is already
+    /// stored, and points `key` at it. A stored blob that is corrupted is replaced.
+    pub fn insert(&mut self, key: CacheKey, binary: &[u8]) -> Result<String> {
+        let digest = digest(binary);
+        let path = self.blob_path(&digest)?;
+        if self.read(&digest).is_err() {
+            fs::create_dir_all(path.parent().unwrap())?;
+            write_atomically(&path, &Encoding::Zstd.encode(binary)?)?;
+        }
+
+        self.link(key, &digest)?;
+
+        Ok(digest)
+    }
+
+    /// Points `key` at an already stored blob.
+    pub fn link(&mut self, key: CacheKey, digest: &str) -> Result<()> {
+        if self.lookup(&key) == Some(digest) {
+            return Ok(());
+        }
+
+        self.entries.retain(|entry| entry.key != key);
+        self.entries.push(CacheEntry {
+            key,
+            digest: digest.to_string(),
+        });
+        self.write_index()
+    }
+
+    fn write_index(&self) -> Result<()> {
+        fs::create_dir_all(&self.root)?;
+        write_atomically(
+            &self.root.join(INDEX_FILE),
+            &serde_json::to_vec_pretty(&self.entries)?,
+        )?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+fn key(id: &str, version: Option<&str>, source: &str) -> CacheKey {
+    CacheKey {
+        id: id.to_string(),
+        version: version.map(|v| v.to_string()),
+        source: source.to_string(),
+    }
+}
+
+#[test]
+fn identical_binaries_are_stored_once() -> Result<()> {
+    let dir = tempdir::TempDir::new("cache")?;
+    let mut cache = Cache::open(dir.path().to_str().unwrap())?;
+
+    let first = cache.insert(key("side", None, "http://a/side.wasm"), b"\0asm")?;
+    let second = cache.insert(key("side", None, "http://b/side.wasm"), b"\0asm")?;
+    assert_eq!(first, second);
+
+    let blobs = fs::read_dir(dir.path().join(BLOB_DIRECTORY).join(DIGEST_ALGORITHM))?.count();
+    assert_eq!(blobs, 1);
+
+    Ok(())
+}
+
+#[test]
+fn versions_coexist_and_survive_reopening() -> Result<()> {
+    let dir = tempdir::TempDir::new("cache")?;
+    let root = dir.path().to_str().unwrap();
+    let mut cache = Cache::open(root)?;
+
+    let old = cache.insert(key("acme/imaging", Some("1.1"), "http://a"), b"old")?;
+    let new = cache.insert(key("acme/imaging", Some("1.2"), "http://a"), b"new")?;
+
+    let cache = Cache::open(root)?;
+    assert_eq!(
+        cache.lookup(&key("acme/imaging", Some("1.1"), "http://a")),
+        Some(old.as_str())
+    );
+    assert_eq!(
+        cache.lookup(&key("acme/imaging", Some("1.2"), "http://a")),
+        Some(new.as_str())
+    );
+    assert_eq!(cache.read(&old)?, b"old");
+    assert_eq!(cache.read(&new)?, b"new");
+
+    Ok(())
+}
+
+#[test]
+fn corrupted_blob_is_rejected() -> Result<()> {
+    let dir = tempdir::TempDir::new("cache")?;
+    let mut cache = Cache::open(dir.path().to_str().unwrap())?;
+
+    let digest = cache.insert(key("side", None, "http://a"), b"\0asm")?;
+    fs::write(cache.blob_path(&digest)?, b"\0as")?;
+    assert!(cache.read(&digest).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn partial_files_are_removed_on_open() -> Result<()> {
+    let dir = tempdir::TempDir::new("cache")?;
+    let root = dir.path().to_str().unwrap();
+    let mut cache = Cache::open(root)?;
+
+    let digest = cache.insert(key("side", None, "http://a"), b"\0asm")?;
+    let blob = cache.blob_path(&digest)?;
+    let partial = blob.with_file_name(format!("abc{}1-0", TEMPORARY_MARKER));
+    fs::write(&partial, b"\0a")?;
+    fs::File::options()
+        .write(true)
+        .open(&partial)?
+        .set_modified(SystemTime::now() - PARTIAL_FILE_AGE)?;
+    // Possibly still being written by another `Manager`.
+    let in_flight = blob.with_file_name(format!("def{}{}-0", TEMPORARY_MARKER, std::process::id()));
+    fs::write(&in_flight, b"\0a")?;
+
+    let cache = Cache::open(root)?;
+    assert!(!partial.exists());
+    assert!(in_flight.exists());
+    assert_eq!(cache.read(&digest)?, b"\0asm");
+
+    Ok(())
+}
+
+#[test]
+fn entries_without_blobs_are_dropped_on_open() -> Result<()> {
+    let dir = tempdir::TempDir::new("cache")?;
+    let root = dir.path().to_str().unwrap();
+    let mut cache = Cache::open(root)?;
+
+    let digest = cache.insert(key("side", None, "http://a"), b"\0asm")?;
+    fs::remove_file(cache.blob_path(&digest)?)?;
+
+    let cache = Cache::open(root)?;
+    assert_eq!(cache.lookup(&key("side", None, "http://a")), None);
+
+    Ok(())
+}
+
+#[test]
+fn blobs_are_stored_compressed() -> Result<()> {
+    let dir = tempdir::TempDir::new("cache")?;
+    let mut cache = Cache::open(dir.path().to_str().unwrap())?;
+
+    let binary = vec![0u8; 4096];
+    let digest = cache.insert(key("side", None, "http://a"), &binary)?;
+    assert!(cache.stored_size(&digest).unwrap() < binary.len() as u64);
+    assert_eq!(cache.read(&digest)?, binary);
+
+    Ok(())
+}
@@ -0,0 +1,204 @@
+use anyhow::{anyhow, Result};
+use serde_derive::{Deserialize, Serialize};
+use std::io::{Read, Write};
+
+/// Encodings the manager can negotiate with a registry, in order of preference.
+pub const ACCEPTED_ENCODINGS: &str = "zstd, br, gzip";
+
+// Level 3 is zstd's default and keeps compression cheap on phones.
+const ZSTD_LEVEL: i32 = 3;
+const BROTLI_QUALITY: u32 = 9;
+const BROTLI_WINDOW: u32 = 22;
+const BUFFER_SIZE: usize = 4096;
+// Decoding stops with an error past this size, so that a small download or cache blob
+// cannot expand to fill the memory of the device.
+const MAX_DECODED_SIZE: u64 = 256 * 1024 * 1024;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Encoding {
+    Identity,
+    Gzip,
+    Zstd,
+    #[serde(rename = "br")]
+    Brotli,
+}
+
+impl Encoding {
+    /// Parses a `Content-Encoding` header value.
+    pub fn from_header(value: &str) -> Result<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "" | "identity" => Ok(Encoding::Identity),
+            "gzip" | "x-gzip" => Ok(Encoding::Gzip),
+            "zstd" => Ok(Encoding::Zstd),
+            "br" => Ok(Encoding::Brotli),
+            other => Err(anyhow!("Unsupported content encoding `{}`", other)),
+        }
+    }
+
+    /// Guesses the encoding of a pre-compressed artifact from its file extension.
+    pub fn from_location(location: &str) -> Self {
+        if location.ends_with(".zst") {
+            Encoding::Zstd
+        } else if location.ends_with(".gz") {
+            Encoding::Gzip
+        } else if location.ends_with(".br") {
+            Encoding::Brotli
+        } else {
+            Encoding::Identity
+        }
+    }
+
+    pub fn decode(self, data: &[u8]) -> Result<Vec<u8>> {
+        self.decode_at_most(data, MAX_DECODED_SIZE)
+    }
+
+    /// Decodes `data`, failing when it decodes to more than `limit` bytes.
+    fn decode_at_most(self, data: &[u8], limit: u64) -> Result<Vec<u8>> {
+        let decoder: Box<dyn Read + '_> = match self {
+            Encoding::Identity => return Ok(data.to_vec()),
+            Encoding::Gzip => Box::new(flate2::read::GzDecoder::new(data)),
+            Encoding::Zstd => Box::new(zstd::stream::read::Decoder::new(data)?),
+            Encoding::Brotli => Box::new(brotli::Decompressor::new(data, BUFFER_SIZE)),
+        };
+        let mut decoded = vec![];
+        decoder.take(limit + 1).read_to_end(&mut decoded)?;
+        if decoded.len() as u64 > limit {
+            return Err(anyhow!(
+                "{:?} data decodes to more than the limit of {} bytes",
+                self,
+                limit
+            ));
+        }
+
+        Ok(decoded)
+    }
+
+    pub fn encode(self, data: &[u8]) -> Result<Vec<u8>> {
+        match self {
+            Encoding::Identity => Ok(data.to_vec()),
+            Encoding::Gzip => {
+                let mut encoder =
+                    flate2::write::GzEncoder::new(vec![], flate2::Compression::default());
+                encoder.write_all(data)?;
+                Ok(encoder.finish()?)
+            }
+            Encoding::Zstd => Ok(zstd::stream::encode_all(data, ZSTD_LEVEL)?),
+            Encoding::Brotli => {
+                let mut encoded = vec![];
+                {
+                    let mut encoder = brotli::CompressorWriter::new(
+                        &mut encoded,
+                        BUFFER_SIZE,
+                        BROTLI_QUALITY,
+                        BROTLI_WINDOW,
+                    );
+                    encoder.write_all(data)?;
+                }
+                Ok(encoded)
+            }
+        }
+    }
+}
+
+/// Decodes a download sent with the `transfer` content encoding. Some servers unpack a
+/// pre-compressed artifact for the transfer and others send it as is, so the `artifact`
+/// encoding is decoded as well unless the result is already a WebAssembly binary.
+/// Returns the data and the encoding it was transferred in.
+pub fn decode_download(
+    data: &[u8],
+    transfer: Encoding,
+    artifact: Encoding,
+) -> Result<(Vec<u8>, Encoding)> {
+    let decoded = transfer.decode(data)?;
+    if artifact == Encoding::Identity || decoded.starts_with(b"\0asm") {
+        return Ok((decoded, transfer));
+    }
+
+    let encoding = if transfer == Encoding::Identity {
+        artifact
+    } else {
+        transfer
+    };
+    Ok((artifact.decode(&decoded)?, encoding))
+}
+
+#[test]
+fn every_encoding_round_trips() -> Result<()> {
+    let binary: Vec<u8> = b"\0asm\x01\0\0\0"
+        .iter()
+        .cycle()
+        .take(4096)
+        .cloned()
+        .collect();
+    for encoding in &[
+        Encoding::Identity,
+        Encoding::Gzip,
+        Encoding::Zstd,
+        Encoding::Brotli,
+    ] {
+        let encoded = encoding.encode(&binary)?;
+        if *encoding != Encoding::Identity {
+            assert!(encoded.len() < binary.len());
+        }
+        assert_eq!(encoding.decode(&encoded)?, binary);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn decoding_stops_at_the_limit() -> Result<()> {
+    let bomb = vec![0u8; 64 * 1024];
+    for encoding in &[Encoding::Gzip, Encoding::Zstd, Encoding::Brotli] {
+        let encoded = encoding.encode(&bomb)?;
+        let error = encoding.decode_at_most(&encoded, 1024).unwrap_err();
+        assert!(error.to_string().contains("more than the limit"));
+        assert_eq!(encoding.decode_at_most(&encoded, bomb.len() as u64)?, bomb);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn encoding_from_header_and_location() -> Result<()> {
+    assert_eq!(Encoding::from_header("gzip")?, Encoding::Gzip);
+    assert_eq!(Encoding::from_header(" br ")?, Encoding::Brotli);
+    assert_eq!(Encoding::from_header("")?, Encoding::Identity);
+    assert!(Encoding::from_header("compress").is_err());
+    assert_eq!(
+        Encoding::from_location("http://a/side.wasm.zst"),
+        Encoding::Zstd
+    );
+    assert_eq!(
+        Encoding::from_location("http://a/side.wasm"),
+        Encoding::Identity
+    );
+
+    Ok(())
+}
+
+#[test]
+fn pre_compressed_downloads_are_decoded_once_or_twice() -> Result<()> {
+    let binary = b"\0asm\x01\0\0\0".to_vec();
+    let artifact = Encoding::Zstd.encode(&binary)?;
+
+    // Served as is, or unpacked by the server for a transfer encoding.
+    assert_eq!(
+        decode_download(&artifact, Encoding::Identity, Encoding::Zstd)?,
+        (binary.clone(), Encoding::Zstd)
+    );
+    let unpacked = Encoding::Gzip.encode(&binary)?;
+    assert_eq!(
+        decode_download(&unpacked, Encoding::Gzip, Encoding::Zstd)?,
+        (binary.clone(), Encoding::Gzip)
+    );
+    // Sent as is with a transfer encoding on top.
+    let both = Encoding::Gzip.encode(&artifact)?;
+    assert_eq!(
+        decode_download(&both, Encoding::Gzip, Encoding::Zstd)?,
+        (binary, Encoding::Gzip)
+    );
+
+    Ok(())
+}
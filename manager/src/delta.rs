@@ -0,0 +1,186 @@
+use anyhow::{anyhow, Result};
+use dylink::leb128;
+use std::collections::HashMap;
+
+// Patch layout:
+//   "WDLT" <version: u8 = 1> <target length: uleb128>
+//   followed by operations until the end of the patch:
+//     0x00 <offset: uleb128> <length: uleb128>   copy bytes of the base
+//     0x01 <length: uleb128> <bytes>             insert literal bytes
+const MAGIC: &[u8; 4] = b"WDLT";
+const VERSION: u8 = 1;
+const COPY: u8 = 0x00;
+const INSERT: u8 = 0x01;
+
+// Shortest run of the base worth referencing instead of inserting.
+const BLOCK_SIZE: usize = 16;
+// Bound on how much larger than its inputs a patched binary may be, so a small patch
+// of repeated copies cannot expand to gigabytes before its digest is checked.
+const MAX_EXPANSION: usize = 4;
+
+// Writing to a `Vec` cannot fail.
+fn write_uleb128(out: &mut Vec<u8>, value: u64) {
+    leb128::write_u64(out, value).expect("writing to a Vec");
+}
+
+fn read_uleb128(patch: &[u8], position: &mut usize) -> Result<u64> {
+    let (value, length) = leb128::decode_u64(&patch[(*position).min(patch.len())..])
+        .map_err(|e| anyhow!("Patch is malformed at offset {}: {}", *position, e))?;
+    *position += length;
+    Ok(value)
+}
+
+fn flush_insert(patch: &mut Vec<u8>, pending: &mut Vec<u8>) {
+    if !pending.is_empty() {
+        patch.push(INSERT);
+        write_uleb128(patch, pending.len() as u64);
+        patch.append(pending);
+    }
+}
+
+/// Creates a patch that turns `base` into `target`.
+pub fn create_patch(base: &[u8], target: &[u8]) -> Vec<u8> {
+    let mut blocks: HashMap<&[u8], usize> = HashMap::new();
+    for offset in (0..base.len().saturating_sub(BLOCK_SIZE - 1)).step_by(BLOCK_SIZE) {
+        blocks
+            .entry(&base[offset..offset + BLOCK_SIZE])
+            .or_insert(offset);
+    }
+
+    let mut patch = MAGIC.to_vec();
+    patch.push(VERSION);
+    write_uleb128(&mut patch, target.len() as u64);
+
+    let mut pending = vec![];
+    let mut position = 0;
+    while position < target.len() {
+        let found = target
+            .get(position..position + BLOCK_SIZE)
+            .and_then(|block| blocks.get(block));
+
+        match found {
+            Some(&offset) => {
+                let length = base[offset..]
+                    .iter()
+                    .zip(&target[position..])
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                flush_insert(&mut patch, &mut pending);
+                patch.push(COPY);
+                write_uleb128(&mut patch, offset as u64);
+                write_uleb128(&mut patch, length as u64);
+                position += length;
+            }
+            None => {
+                pending.push(target[position]);
+                position += 1;
+            }
+        }
+    }
+    flush_insert(&mut patch, &mut pending);
+
+    patch
+}
+
+/// Applies a patch made by `create_patch` to `base`.
+pub fn apply_patch(base: &[u8], patch: &[u8]) -> Result<Vec<u8>> {
+    if patch.len() < MAGIC.len() + 1 || &patch[..MAGIC.len()] != MAGIC {
+        return Err(anyhow!("Not a module patch"));
+    }
+    if patch[MAGIC.len()] != VERSION {
+        return Err(anyhow!("Unsupported patch version {}", patch[MAGIC.len()]));
+    }
+
+    let mut position = MAGIC.len() + 1;
+    let target_length = read_uleb128(patch, &mut position)? as usize;
+    let max_length = MAX_EXPANSION.saturating_mul(base.len().saturating_add(patch.len()));
+    if target_length > max_length {
+        return Err(anyhow!(
+            "Patch produces {} bytes, more than the limit of {}",
+            target_length,
+            max_length
+        ));
+    }
+    let mut target = Vec::with_capacity(target_length);
+
+    while position < patch.len() {
+        let operation = patch[position];
+        position += 1;
+        let (source, start, length) = match operation {
+            COPY => {
+                let offset = read_uleb128(patch, &mut position)? as usize;
+                let length = read_uleb128(patch, &mut position)? as usize;
+                (base, offset, length)
+            }
+            INSERT => {
+                let length = read_uleb128(patch, &mut position)? as usize;
+                let start = position;
+                position = position.saturating_add(length);
+                (patch, start, length)
+            }
+            other => return Err(anyhow!("Unknown patch operation {:#x}", other)),
+        };
+
+        let bytes = start
+            .checked_add(length)
+            .and_then(|end| source.get(start..end))
+            .ok_or(anyhow!("Patch refers to bytes outside of its input"))?;
+        if target.len() + bytes.len() > target_length {
+            return Err(anyhow!("Patch produces more than {} bytes", target_length));
+        }
+        target.extend_from_slice(bytes);
+    }
+
+    if target.len() != target_length {
+        return Err(anyhow!(
+            "Patch produced {} bytes instead of {}",
+            target.len(),
+            target_length
+        ));
+    }
+
+    Ok(target)
+}
+
+#[test]
+fn patch_round_trips() -> Result<()> {
+    let base: Vec<u8> = (0..4096u32).map(|i| (i * 7 % 251) as u8).collect();
+    let mut target = base.clone();
+    target[100] ^= 0xff;
+    target.splice(2000..2000, b"inserted".iter().cloned());
+    target.truncate(3900);
+
+    let patch = create_patch(&base, &target);
+    assert!(patch.len() < target.len() / 10);
+    assert_eq!(apply_patch(&base, &patch)?, target);
+    assert_eq!(apply_patch(&base, &create_patch(&base, &[]))?, b"");
+    assert_eq!(apply_patch(&[], &create_patch(&[], &target))?, target);
+
+    Ok(())
+}
+
+#[test]
+fn malformed_patches_are_rejected() {
+    let base = vec![1u8; 64];
+    let patch = create_patch(&base, &[1u8; 80]);
+
+    assert!(apply_patch(&base, b"WDL").is_err());
+    assert!(apply_patch(&base, &patch[..patch.len() - 1]).is_err());
+    assert!(apply_patch(&base[..32], &patch).is_err());
+    // Copy of 127 bytes from offset 0 of a 64 byte base.
+    assert!(apply_patch(&base, b"WDLT\x01\x7f\x00\x00\x7f").is_err());
+}
+
+#[test]
+fn expanding_patches_are_rejected() {
+    let base = vec![1u8; 64];
+    // A gigabyte made of copies of the whole base.
+    let mut patch = b"WDLT\x01".to_vec();
+    write_uleb128(&mut patch, 1 << 30);
+    for _ in 0..1000 {
+        patch.extend_from_slice(&[COPY, 0x00, 0x40]);
+    }
+
+    let error = apply_patch(&base, &patch).unwrap_err();
+    assert!(error.to_string().contains("more than the limit"));
+}
@@ -0,0 +1,165 @@
+use anyhow::{anyhow, Result};
+use std::fmt;
+use std::str::FromStr;
+
+/// A module identifier of the form `[namespace/]name[@version]`,
+/// for example `acme/imaging@1.2`, `side` or `marvin@0.0.1`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ModuleId {
+    pub namespace: Option<String>,
+    pub name: String,
+    pub version: Option<String>,
+}
+
+/// Segments made only of dots, such as `..`, are rejected, as ids end up in paths.
+fn valid_segment(segment: &str) -> bool {
+    !segment.is_empty()
+        && !segment.chars().all(|c| c == '.')
+        && segment
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '.')
+}
+
+impl ModuleId {
+    pub fn parse(id: &str) -> Result<Self> {
+        let (path, version) = match id.find('@') {
+            Some(index) => (&id[..index], Some(&id[index + 1..])),
+            None => (id, None),
+        };
+
+        let (namespace, name) = match path.find('/') {
+            Some(index) => (Some(&path[..index]), &path[index + 1..]),
+            None => (None, path),
+        };
+
+        if let Some(namespace) = namespace {
+            if !valid_segment(namespace) {
+                return Err(anyhow!("Invalid namespace in module id `{}`", id));
+            }
+        }
+        if !valid_segment(name) {
+            return Err(anyhow!("Invalid name in module id `{}`", id));
+        }
+        if let Some(version) = version {
+            if version.is_empty()
+                || version.chars().all(|c| c == '.')
+                || version.contains('/')
+                || version.contains('@')
+            {
+                return Err(anyhow!("Invalid version in module id `{}`", id));
+            }
+        }
+
+        Ok(Self {
+            namespace: namespace.map(|n| n.to_string()),
+            name: name.to_string(),
+            version: version.map(|v| v.to_string()),
+        })
+    }
+
+    /// The id without its version, e.g. `acme/imaging`.
+    pub fn qualified_name(&self) -> String {
+        match &self.namespace {
+            Some(namespace) => format!("{}/{}", namespace, self.name),
+            None => self.name.clone(),
+        }
+    }
+
+    /// Places an unqualified id into `namespace`. Qualified ids are returned unchanged.
+    pub fn in_namespace(&self, namespace: &str) -> Self {
+        let mut id = self.clone();
+        if id.namespace.is_none() {
+            id.namespace = Some(namespace.to_string());
+        }
+        id
+    }
+
+    /// Form of the id that can be used as a single path segment of a registry URL.
+    pub fn url_segment(&self) -> String {
+        self.to_string().replace('/', "%2F")
+    }
+}
+
+/// Module id of a library named in a binary's `needed_dynlibs`, e.g. `side` for `side.wasm`.
+pub fn library_id(library: &str) -> &str {
+    library
+        .strip_suffix(".wasm")
+        .or_else(|| library.strip_suffix(".so"))
+        .unwrap_or(library)
+}
+
+impl fmt::Display for ModuleId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.qualified_name())?;
+        if let Some(version) = &self.version {
+            write!(f, "@{}", version)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for ModuleId {
+    type Err = anyhow::Error;
+
+    fn from_str(id: &str) -> Result<Self> {
+        ModuleId::parse(id)
+    }
+}
+
+#[test]
+fn parse_plain_and_versioned_ids() -> Result<()> {
+    let side = ModuleId::parse("side")?;
+    assert_eq!(side.namespace, None);
+    assert_eq!(side.name, "side");
+    assert_eq!(side.version, None);
+
+    let marvin = ModuleId::parse("marvin@0.0.1")?;
+    assert_eq!(marvin.name, "marvin");
+    assert_eq!(marvin.version.as_deref(), Some("0.0.1"));
+    assert_eq!(marvin.to_string(), "marvin@0.0.1");
+
+    Ok(())
+}
+
+#[test]
+fn parse_namespaced_id() -> Result<()> {
+    let imaging = ModuleId::parse("acme/imaging@1.2")?;
+    assert_eq!(imaging.namespace.as_deref(), Some("acme"));
+    assert_eq!(imaging.name, "imaging");
+    assert_eq!(imaging.version.as_deref(), Some("1.2"));
+    assert_eq!(imaging.qualified_name(), "acme/imaging");
+    assert_eq!(imaging.url_segment(), "acme%2Fimaging@1.2");
+    assert_eq!(
+        ModuleId::parse("imaging")?.in_namespace("acme"),
+        ModuleId::parse("acme/imaging")?
+    );
+
+    Ok(())
+}
+
+#[test]
+fn library_names_map_to_ids() {
+    assert_eq!(library_id("side.wasm"), "side");
+    assert_eq!(library_id("libimaging.so"), "libimaging");
+    assert_eq!(library_id("acme/imaging@1.2"), "acme/imaging@1.2");
+}
+
+#[test]
+fn reject_malformed_ids() {
+    assert!(ModuleId::parse("").is_err());
+    assert!(ModuleId::parse("acme/").is_err());
+    assert!(ModuleId::parse("/imaging").is_err());
+    assert!(ModuleId::parse("a/b/c").is_err());
+    assert!(ModuleId::parse("imaging@").is_err());
+}
+
+#[test]
+fn reject_dot_segments() {
+    assert!(ModuleId::parse("..").is_err());
+    assert!(ModuleId::parse("../x").is_err());
+    assert!(ModuleId::parse("./x").is_err());
+    assert!(ModuleId::parse("acme/..").is_err());
+    assert!(ModuleId::parse("acme/...").is_err());
+    assert!(ModuleId::parse("x@..").is_err());
+    assert!(ModuleId::parse("acme.io/lib.v2@1.2").is_ok());
+}
@@ -1,90 +1,284 @@
 #[macro_use]
 extern crate log;
 
+mod cache;
+mod compression;
+mod delta;
+mod id;
+mod metadata;
+mod publish;
+
+pub use cache::digest;
+pub use compression::Encoding;
+pub use delta::{apply_patch, create_patch};
+pub use id::{library_id, ModuleId};
+pub use metadata::{select, Artifact, Closure, Dependency, Metadata, ModuleSummary, Patch};
+pub use publish::{publish, PublishRequest, PublishResponse};
+
 use anyhow::{anyhow, Result};
-use regex::Regex;
-use serde_derive::Deserialize;
-use std::collections::HashMap;
-use std::fs;
-use std::io::Write;
-use std::path::Path;
+use cache::{Cache, CacheKey};
+use compression::ACCEPTED_ENCODINGS;
+use metadata::artifact_encoding;
+use reqwest::header::{ACCEPT_ENCODING, CONTENT_ENCODING};
+use reqwest::StatusCode;
+use std::collections::{HashMap, HashSet};
 
 pub struct Manager {
     metadata: HashMap<String, Metadata>,
     attributes: Vec<String>,
-    cache: Option<String>,
-    modules: Vec<String>,
+    cache: Cache,
+    // Namespace -> registry location, e.g. "acme" -> "http://registry.acme.com/api"
+    registries: HashMap<String, String>,
+    stats: Stats,
+    // Metadata received in a closure, with the registry location it came from.
+    prefetched: HashMap<String, (String, Metadata)>,
+    // Registry locations that do not implement the closure endpoint.
+    closure_unsupported: HashSet<String>,
 }
 
-#[derive(Deserialize, Debug, Clone)]
-struct Metadata {
-    id: String,
-    attributes: Vec<String>,
-    location: String,
-    dependencies: HashMap<String, Dependency>,
+/// Sizes of one module binary downloaded by the manager.
+#[derive(Debug, Clone)]
+pub struct TransferStats {
+    pub id: String,
+    pub encoding: Encoding,
+    // Bytes received over the network.
+    pub transferred_bytes: u64,
+    // Size of the decompressed binary.
+    pub decoded_bytes: u64,
+    // Size of the binary in the cache.
+    pub stored_bytes: u64,
+    // Digest of the cached binary a downloaded patch was applied to.
+    pub patched_from: Option<String>,
 }
 
-#[derive(Deserialize, Debug, Clone)]
-pub struct Dependency {
-    pub id: String,
-    #[serde(alias = "loadTime")]
-    load_time: bool,
-    location: String,
+#[derive(Debug, Clone, Default)]
+pub struct Stats {
+    pub transfers: Vec<TransferStats>,
 }
 
-impl PartialEq for Dependency {
-    fn eq(&self, other: &Self) -> bool {
-        self.id == other.id && self.load_time == other.load_time && self.location == other.location
+impl Stats {
+    pub fn transferred_bytes(&self) -> u64 {
+        self.transfers.iter().map(|t| t.transferred_bytes).sum()
     }
-}
 
-impl Metadata {
-    pub(crate) fn new(json: String) -> Result<Self> {
-        let metadata: Metadata = serde_json::from_str(&json)?;
+    pub fn decoded_bytes(&self) -> u64 {
+        self.transfers.iter().map(|t| t.decoded_bytes).sum()
+    }
 
-        Ok(metadata)
+    pub fn stored_bytes(&self) -> u64 {
+        self.transfers.iter().map(|t| t.stored_bytes).sum()
     }
 }
 
 impl Manager {
     pub fn new(metafile: &str, attributes: Vec<String>, cache: &Option<String>) -> Result<Self> {
         let metadata = Metadata::new(metafile.to_string())?;
-        let modules = Manager::check_cached_modules(&cache.as_ref().unwrap()).unwrap_or_else(|_| vec![]);
+        let cache = Cache::open(cache.as_ref().unwrap())?;
+        let registries = metadata.registries.clone();
         let mut metadata_map = HashMap::new();
         metadata_map.insert(metadata.id.clone(), metadata.clone());
 
-        Ok(Self { metadata: metadata_map, attributes, cache: Some(cache.as_ref().unwrap().clone()), modules })
+        Ok(Self {
+            metadata: metadata_map,
+            attributes,
+            cache,
+            registries,
+            stats: Stats::default(),
+            prefetched: HashMap::new(),
+            closure_unsupported: HashSet::new(),
+        })
+    }
+
+    /// Routes every module of `namespace` to the registry at `location`
+    /// instead of the location written in the dependency metadata.
+    pub fn add_registry(&mut self, namespace: &str, location: &str) {
+        self.registries
+            .insert(namespace.to_string(), location.to_string());
     }
 
+    /// Resolves `id` as seen from `caller_module`. An exact match in the caller's
+    /// dependencies wins. Otherwise an unqualified id is looked up in the caller's
+    /// namespace, and an id without a version matches a single versioned dependency.
     pub fn resolve_id(&mut self, caller_module: &str, id: &str) -> Result<Dependency> {
         let module = self.metadata.get(caller_module).ok_or(anyhow!(
             "Cannot find the desired module: `{}` defined in the metadata",
             caller_module
         ))?;
 
-        let dependency = module.dependencies.get(id).ok_or(anyhow!(
-            "Cannot find the desired module's dependency `{}` defined in the metadata of module {}",
-            id, caller_module
-        ))?;
+        if let Some(dependency) = module.dependencies.get(id) {
+            return Ok(dependency.clone());
+        }
+
+        let mut wanted = ModuleId::parse(id)?;
+        if let Ok(caller) = ModuleId::parse(caller_module) {
+            if let Some(namespace) = &caller.namespace {
+                wanted = wanted.in_namespace(namespace);
+            }
+        }
 
-        return Ok(dependency.clone());
+        let candidates: Vec<&Dependency> = module
+            .dependencies
+            .iter()
+            .filter(|(key, _)| match ModuleId::parse(key) {
+                Ok(key) => {
+                    key.qualified_name() == wanted.qualified_name()
+                        && (wanted.version.is_none() || key.version == wanted.version)
+                }
+                Err(_) => false,
+            })
+            .map(|(_, dependency)| dependency)
+            .collect();
+
+        match candidates.as_slice() {
+            [dependency] => Ok((*dependency).clone()),
+            [] => Err(anyhow!(
+                "Cannot find the desired module's dependency `{}` defined in the metadata of module {}",
+                id, caller_module
+            )),
+            _ => Err(anyhow!(
+                "The dependency `{}` of module {} is ambiguous, specify a version",
+                id, caller_module
+            )),
+        }
+    }
+
+    fn registry_location(&self, dependency: &Dependency) -> String {
+        ModuleId::parse(&dependency.id)
+            .ok()
+            .and_then(|id| id.namespace)
+            .and_then(|namespace| self.registries.get(&namespace).cloned())
+            .unwrap_or_else(|| dependency.location.clone())
     }
 
     fn load_metadata(&mut self, name: &str, location: &str) -> Result<Vec<u8>> {
         let client = reqwest::blocking::Client::new();
-        let url = format!("{}/{}", location, name);
+        let url = format!("{}/{}", location, ModuleId::parse(name)?.url_segment());
         info!("loading metadata from {}", url);
         let attributes: Vec<&str> = self.attributes.iter().map(|a| a.as_str()).collect();
-        let attributes: HashMap<&str, Vec<&str>> = [("attributes", attributes)].iter().cloned().collect();
-        let file = client.post(&url).json(&attributes).send()?.bytes()?.to_vec();
+        let attributes: HashMap<&str, Vec<&str>> =
+            [("attributes", attributes)].iter().cloned().collect();
+        let file = client
+            .post(&url)
+            .json(&attributes)
+            .send()?
+            .bytes()?
+            .to_vec();
 
         Ok(file)
     }
 
+    /// Asks the registry at `location` for the whole load-time dependency closure of `id`
+    /// in one request. The received metadata is only used once `fetch_metadata` has
+    /// checked it, and modules routed to other registries are still fetched from those.
+    /// Returns false when the registry does not implement the endpoint.
+    fn load_closure(&mut self, id: &str, location: &str) -> Result<bool> {
+        let client = reqwest::blocking::Client::new();
+        let url = format!(
+            "{}/closure/{}",
+            location,
+            ModuleId::parse(id)?.url_segment()
+        );
+        info!("loading dependency closure from {}", url);
+        let attributes: HashMap<&str, &Vec<String>> =
+            [("attributes", &self.attributes)].iter().cloned().collect();
+        let response = client.post(&url).json(&attributes).send()?;
+        match response.status() {
+            StatusCode::NOT_FOUND
+            | StatusCode::METHOD_NOT_ALLOWED
+            | StatusCode::NOT_IMPLEMENTED => return Ok(false),
+            status if !status.is_success() => {
+                return Err(anyhow!("{} answered {}: {}", url, status, response.text()?))
+            }
+            _ => {}
+        }
+
+        let closure: Closure = response.json()?;
+        if closure.root != id {
+            return Err(anyhow!(
+                "Requested the closure of {}, but got the closure of {}",
+                id,
+                closure.root
+            ));
+        }
+
+        for metadata in closure.modules {
+            self.prefetched
+                .insert(metadata.id.clone(), (location.to_string(), metadata));
+        }
+
+        Ok(true)
+    }
+
+    /// Metadata of `id` from the registry at `location`, preferring a prefetched closure.
+    fn fetch_metadata(&mut self, id: &str, location: &str) -> Result<Metadata> {
+        let metadata = match self.prefetched.remove(id) {
+            Some((source, metadata)) if source == location => metadata,
+            _ => serde_json::from_slice(&self.load_metadata(id, location)?)?,
+        };
+
+        if metadata.id != id {
+            return Err(anyhow!(
+                "The registry at {} answered a request for {} with the metadata of {}",
+                location,
+                id,
+                metadata.id
+            ));
+        }
+        if !metadata.supports(&self.attributes) {
+            return Err(anyhow!(
+                "The registry at {} selected metadata of {} requiring attributes {:?}, but this platform has {:?}",
+                location, id, metadata.attributes, self.attributes
+            ));
+        }
+
+        Ok(metadata)
+    }
+
+    /// Lists the modules of the registry at `location`, e.g. `http://localhost:3000/api`,
+    /// that have all of `attributes` and can run on this platform.
+    pub fn list_modules(
+        &self,
+        location: &str,
+        attributes: &[String],
+    ) -> Result<Vec<ModuleSummary>> {
+        let client = reqwest::blocking::Client::new();
+        let url = format!("{}/modules", location);
+        info!("listing modules from {}", url);
+        let query: Vec<(&str, &str)> = attributes
+            .iter()
+            .map(|a| ("attribute", a.as_str()))
+            .collect();
+        let response = client.get(&url).query(&query).send()?;
+        if !response.status().is_success() {
+            return Err(anyhow!(
+                "{} answered {}: {}",
+                url,
+                response.status(),
+                response.text()?
+            ));
+        }
+
+        let modules: Vec<ModuleSummary> = response.json()?;
+
+        Ok(modules
+            .into_iter()
+            .filter(|module| {
+                module
+                    .attributes
+                    .iter()
+                    .all(|a| self.attributes.contains(a))
+            })
+            .collect())
+    }
+
     pub fn get_attributes(&self) -> Vec<String> {
         self.attributes.clone()
     }
 
+    pub fn stats(&self) -> Stats {
+        self.stats.clone()
+    }
+
     fn analyse_module(
         &mut self,
         module: &Metadata,
@@ -93,18 +287,17 @@ impl Manager {
         let deps: Vec<Dependency> = module
             .dependencies
             .iter()
-            .filter(|d| d.1.load_time && !dependencies.contains(&d.1))
+            .filter(|d| d.1.load_time && !dependencies.contains(d.1))
             .map(|dep| dep.1.clone())
             .collect();
 
         dependencies.extend(deps.clone());
 
         for d in deps {
-            let dependency = self.load_metadata(&d.id, &d.location)?;
-            let metadata: Metadata = serde_json::from_slice(&dependency)?;
+            let location = self.registry_location(&d);
+            let metadata = self.fetch_metadata(&d.id, &location)?;
 
-            self.metadata
-                .insert(d.id.clone(), metadata.clone());
+            self.metadata.insert(d.id.clone(), metadata.clone());
             self.analyse_module(&metadata, dependencies)?;
         }
 
@@ -120,10 +313,9 @@ impl Manager {
         let module = self.metadata.get(caller_module).cloned();
 
         if module.is_none() {
-            let metadata = self.load_metadata(caller_module, location)?;
-            // println!("{}", String::from_utf8(metadata.clone()).unwrap());
-            let metadata: Metadata = serde_json::from_slice(&metadata)?;
-            self.metadata.insert(caller_module.to_string(), metadata.clone());
+            let metadata = self.fetch_metadata(caller_module, location)?;
+            self.metadata
+                .insert(caller_module.to_string(), metadata.clone());
             self.analyse_module(&metadata, choices)?;
 
             return Ok(());
@@ -134,18 +326,58 @@ impl Manager {
         Ok(())
     }
 
+    /// The `weakImports` of the metadata of the loaded module `id`.
+    pub fn weak_imports(&self, id: &str) -> Vec<String> {
+        self.metadata
+            .get(id)
+            .map(|module| module.weak_imports.clone())
+            .unwrap_or_default()
+    }
+
     pub fn load_main(&mut self, name: &str) -> Result<Vec<u8>> {
         self.retrieve_binary(name)
     }
 
     fn retrieve_binary(&mut self, choice: &str) -> Result<Vec<u8>> {
-        if self.modules.iter().any(|m| m == choice) {
-            let binary = self.load_from_cache(&choice)?;
-            Ok(binary)
-        } else {
-            let binary = self.load_from_registry(&choice)?;
-            Ok(binary)
+        let module = self
+            .metadata
+            .get(choice)
+            .ok_or(anyhow!(
+                "Cannot find the desired module: `{}` defined in the metadata",
+                choice
+            ))?
+            .clone();
+        let key = Manager::cache_key(&module)?;
+
+        // A digest in the metadata names the exact binary we want, otherwise
+        // whatever was last stored for the same id, version and source is used.
+        let cached = match &module.digest {
+            Some(digest) if self.cache.contains(digest) => Some(digest.clone()),
+            Some(_) => None,
+            None => self.cache.lookup(&key).map(|digest| digest.to_string()),
+        };
+
+        if let Some(digest) = cached {
+            match self.cache.read(&digest) {
+                Ok(binary) => {
+                    self.cache.link(key, &digest)?;
+                    return Ok(binary);
+                }
+                Err(e) => warn!("{}, downloading it again", e),
+            }
         }
+
+        self.load_from_registry(&module, key)
+    }
+
+    fn cache_key(module: &Metadata) -> Result<CacheKey> {
+        let id = ModuleId::parse(&module.id)?;
+
+        Ok(CacheKey {
+            id: id.qualified_name(),
+            version: id.version.or_else(|| module.version.clone()),
+            source: module.location.clone(),
+        })
     }
 
     fn load_all_dependencies(
@@ -161,123 +393,315 @@ impl Manager {
         Ok(dependency_tuples)
     }
 
-    pub fn load(
-        &mut self,
-        standalone: Dependency
-    ) -> Result<Vec<(Dependency, Vec<u8>)>> {
+    pub fn load(&mut self, standalone: Dependency) -> Result<Vec<(Dependency, Vec<u8>)>> {
         let mut choices = vec![];
         // The main dependency that needs to be inserted to the very end.
         choices.push(standalone.clone());
-        self.gather_dependency_information(&standalone.id, &standalone.location, &mut choices)?;
+        let location = self.registry_location(&standalone);
+        if !self.closure_unsupported.contains(&location) {
+            match self.load_closure(&standalone.id, &location) {
+                Ok(true) => {}
+                Ok(false) => {
+                    info!(
+                        "{} has no closure endpoint, loading metadata one module at a time",
+                        location
+                    );
+                    self.closure_unsupported.insert(location.clone());
+                }
+                Err(e) => info!(
+                    "Falling back to loading metadata one module at a time: {}",
+                    e
+                ),
+            }
+        }
+        let gathered = self.gather_dependency_information(&standalone.id, &location, &mut choices);
+        self.prefetched.clear();
+        gathered?;
         let all_dependencies = self.load_all_dependencies(choices)?;
 
         Ok(all_dependencies)
     }
 
-    fn load_from_cache(&self, name: &str) -> Result<Vec<u8>> {
-        let filename = format!("{}/{}.wasm", self.cache.as_ref().unwrap(), name);
-        let file = fs::read(filename)?;
+    fn load_from_registry(&mut self, module: &Metadata, key: CacheKey) -> Result<Vec<u8>> {
+        if let Some(binary) = self.load_with_patch(module, &key) {
+            return Ok(binary);
+        }
+
+        let (location, artifact) = match &module.compressed {
+            Some(artifact) => (
+                artifact.location.clone(),
+                artifact_encoding(&artifact.location, artifact.encoding),
+            ),
+            None => (module.location.clone(), Encoding::Identity),
+        };
+        let (binary, encoding, transferred_bytes) = self.download(&location, artifact)?;
+
+        if let Some(expected) = &module.digest {
+            let actual = digest(&binary);
+            if &actual != expected {
+                return Err(anyhow!(
+                    "The binary of module {} from {} has digest {}, but the metadata expects {}",
+                    module.id,
+                    location,
+                    actual,
+                    expected
+                ));
+            }
+        }
+
+        self.store_download(module, key, &binary, encoding, transferred_bytes, None)?;
 
-        return Ok(file);
+        Ok(binary)
     }
 
-    fn load_from_registry(&mut self, name: &str) -> Result<Vec<u8>> {
-        let module = self
-            .metadata
-            .get(name)
-            .ok_or(anyhow!(
-                "Cannot find the desired module: `{}` defined in the metadata",
-                name
-            ))?
-            .clone();
-        info!("Fetching binary from {}", &module.location);
-        let mut binary = reqwest::blocking::get(&module.location)?;
-        let mut buffer: Vec<u8> = vec![];
+    /// Rebuilds the binary from a cached earlier version and a patch listed in the metadata.
+    /// Returns `None` when no patch applies or anything goes wrong, so that the caller
+    /// falls back to downloading the whole binary.
+    fn load_with_patch(&mut self, module: &Metadata, key: &CacheKey) -> Option<Vec<u8>> {
+        let target = module.digest.as_ref()?;
+        let patch = module
+            .patches
+            .iter()
+            .find(|patch| self.cache.contains(&patch.from))?;
+
+        let patched = || -> Result<(Vec<u8>, Encoding, u64)> {
+            let base = self.cache.read(&patch.from)?;
+            let encoding = artifact_encoding(&patch.location, patch.encoding);
+            let (data, encoding, transferred_bytes) = self.download(&patch.location, encoding)?;
+            let binary = apply_patch(&base, &data)?;
+
+            let actual = digest(&binary);
+            if &actual != target {
+                return Err(anyhow!(
+                    "the patched binary has digest {}, but the metadata expects {}",
+                    actual,
+                    target
+                ));
+            }
 
-        if !binary.status().is_success() {
-            // TODO: This should be more specific.
-            return Err(anyhow!(format!("Problem occurred while downloading the module {} from {}", name, module.location)));
-        }
+            Ok((binary, encoding, transferred_bytes))
+        };
 
-        binary.copy_to(&mut buffer)?;
-        buffer.as_slice().to_vec();
-        self.cache_module(&name, buffer.clone())?;
+        let stored = patched().and_then(|(binary, encoding, transferred_bytes)| {
+            let from = Some(patch.from.clone());
+            self.store_download(
+                module,
+                key.clone(),
+                &binary,
+                encoding,
+                transferred_bytes,
+                from,
+            )?;
+            Ok(binary)
+        });
 
-        Ok(buffer)
+        match stored {
+            Ok(binary) => Some(binary),
+            Err(e) => {
+                warn!(
+                    "Patching {} from {} failed, falling back to a full download: {}",
+                    module.id, patch.from, e
+                );
+                None
+            }
+        }
     }
 
-    fn cache_module(&mut self, module: &str, binary: Vec<u8>) -> Result<()> {
-        let cache_path = Path::new(&self.cache.as_ref().unwrap().clone()).to_owned();
-        let filename = format!("{}.wasm", module);
-        let full_pathname = format!("{}/{}", cache_path.display(), filename);
-        let fullpath = Path::new(&full_pathname);
+    /// Downloads `location`, negotiating a content encoding with the server. An `artifact`
+    /// encoding is the encoding of the file itself, as with pre-compressed `.wasm.zst` files.
+    /// Returns the decoded data, the encoding it was transferred in and the transferred size.
+    fn download(&self, location: &str, artifact: Encoding) -> Result<(Vec<u8>, Encoding, u64)> {
+        info!("Fetching {}", location);
+        let client = reqwest::blocking::Client::new();
+        let mut response = client
+            .get(location)
+            .header(ACCEPT_ENCODING, ACCEPTED_ENCODINGS)
+            .send()?;
+        let mut buffer: Vec<u8> = vec![];
 
-        if !cache_path.clone().exists() {
-            fs::create_dir_all(cache_path.clone())?;
+        if !response.status().is_success() {
+            // TODO: This should be more specific.
+            return Err(anyhow!(format!(
+                "Problem occurred while downloading {}: {}",
+                location,
+                response.status()
+            )));
         }
 
-        if cache_path.exists() && !fullpath.exists() {
-            let mut file = fs::File::create(fullpath)?;
-            file.write_all(binary.as_slice())?;
+        let encoding = match response.headers().get(CONTENT_ENCODING) {
+            Some(value) => Encoding::from_header(value.to_str()?)?,
+            None => Encoding::Identity,
+        };
+
+        response.copy_to(&mut buffer)?;
+        let transferred_bytes = buffer.len() as u64;
 
-            self.modules.push(module.to_string());
+        let (decoded, encoding) = compression::decode_download(&buffer, encoding, artifact)?;
+        Ok((decoded, encoding, transferred_bytes))
+    }
 
-            return Ok(());
-        }
+    fn store_download(
+        &mut self,
+        module: &Metadata,
+        key: CacheKey,
+        binary: &[u8],
+        encoding: Encoding,
+        transferred_bytes: u64,
+        patched_from: Option<String>,
+    ) -> Result<()> {
+        let digest = self.cache.insert(key, binary)?;
+        let transfer = TransferStats {
+            id: module.id.clone(),
+            encoding,
+            transferred_bytes,
+            decoded_bytes: binary.len() as u64,
+            stored_bytes: self.cache.stored_size(&digest).unwrap_or(0),
+            patched_from,
+        };
+        info!(
+            "Downloaded {} ({:?}): {} bytes transferred, {} bytes decoded, {} bytes stored",
+            transfer.id,
+            transfer.encoding,
+            transfer.transferred_bytes,
+            transfer.decoded_bytes,
+            transfer.stored_bytes
+        );
+        self.stats.transfers.push(transfer);
 
         Ok(())
     }
+}
 
-    fn check_cached_modules(cache: &str) -> Result<Vec<String>> {
-        let pattern = Regex::new(r"^(?P<name>.+?).wasm")?;
-
-        match fs::read_dir(cache) {
-            Ok(modules) => {
-                let modules = modules
-                    .map(|module| -> Result<String> {
-                        // TODO: This should be revised
-                        // There could be problems with OsString and DirEntry.
-                        let name = module?.file_name();
-                        let name = name.to_str().unwrap();
-                        let captures = pattern.captures(name).unwrap();
-                        let data = captures.name("name").unwrap().as_str();
-
-                        Ok(data.to_string())
-                    })
-                    .filter_map(Result::ok)
-                    .collect::<Vec<_>>();
-
-                Ok(modules)
+#[cfg(test)]
+type ServedFiles = std::sync::Arc<std::sync::Mutex<HashMap<String, Vec<u8>>>>;
+
+/// Serves the files put into the returned map over HTTP, by path, e.g. `/api/marvin`,
+/// and answers 404 for any other path.
+#[cfg(test)]
+fn serve() -> Result<(String, ServedFiles)> {
+    use std::io::{BufRead, BufReader, Read, Write};
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0")?;
+    let base = format!("http://{}", listener.local_addr()?);
+    let files = ServedFiles::default();
+    let served = files.clone();
+    std::thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let mut reader = BufReader::new(&stream);
+            let mut request = String::new();
+            if reader.read_line(&mut request).is_err() {
+                continue;
             }
-            Err(e) => {
-                // QUESTION: Should we just create a cache directory here instead of throwing an error?
-                Err(anyhow!("No existing cache detected. {}", e))
+            let mut length = 0;
+            loop {
+                let mut header = String::new();
+                if reader.read_line(&mut header).is_err() || header.trim().is_empty() {
+                    break;
+                }
+                if let Some((name, value)) = header.split_once(':') {
+                    if name.eq_ignore_ascii_case("content-length") {
+                        length = value.trim().parse().unwrap_or(0);
+                    }
+                }
             }
+            let _ = reader.by_ref().take(length).read_to_end(&mut vec![]);
+
+            let path = request.split(' ').nth(1).unwrap_or_default();
+            let response = match served.lock().unwrap().get(path) {
+                Some(body) => [
+                    format!(
+                        "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                        body.len()
+                    )
+                    .into_bytes(),
+                    body.clone(),
+                ]
+                .concat(),
+                None => b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+                    .to_vec(),
+            };
+            let _ = (&stream).write_all(&response);
         }
-    }
+    });
+
+    Ok((base, files))
+}
+
+/// Serves `marvin@0.0.1` and returns the metadata of `example_main`, which needs it at
+/// load time.
+#[cfg(test)]
+fn serve_example() -> Result<(String, ServedFiles)> {
+    let (base, files) = serve()?;
+    let json = format!(
+        r#"
+        {{
+            "id": "example_main",
+            "location": "{0}/example_main.wasm",
+            "dependencies": {{
+                "marvin@0.0.1": {{
+                    "loadTime": true,
+                    "id": "marvin@0.0.1",
+                    "location": "{0}/api"
+                }}
+            }}
+        }}
+    "#,
+        base
+    );
+    let marvin = format!(
+        r#"{{ "id": "marvin@0.0.1", "location": "{}/marvin.wasm" }}"#,
+        base
+    );
+    files
+        .lock()
+        .unwrap()
+        .insert("/api/marvin@0.0.1".to_string(), marvin.into_bytes());
+    files
+        .lock()
+        .unwrap()
+        .insert("/marvin.wasm".to_string(), b"\0asm".to_vec());
+
+    Ok((json, files))
 }
 
 #[test]
 fn test_loading() -> Result<()> {
-    let json = r#"
-        {
-            "cache": "./cache", 
-            "modules": {
-                "example_main": {
-                    "location": "http://localhost:8080",
-                    "dependencies": {
-                        "marvin@0.0.1": {
-                            "loadTime": true,
-                            "id": "marvin@0.0.1",
-                            "location": "http://localhost:8080/metadata_splitting"
-                        }
-                    }
-                }
-            }
-        }
-    "#;
-    let mut manager = Manager::new(json, &Some("./cache".to_string()))?;
-    manager.load("example_main".to_string(), "marvin@0.0.1".to_string())?;
+    let (json, _files) = serve_example()?;
+    let cache = tempdir::TempDir::new("cache")?;
+    let mut manager = Manager::new(
+        &json,
+        vec![],
+        &Some(cache.path().to_string_lossy().to_string()),
+    )?;
+    let dependency = manager.resolve_id("example_main", "marvin@0.0.1")?;
+    let loaded = manager.load(dependency.clone())?;
+    assert_eq!(loaded, vec![(dependency, b"\0asm".to_vec())]);
+
+    Ok(())
+}
+
+#[test]
+fn corrupted_blob_is_repaired_by_the_next_load() -> Result<()> {
+    let (json, _files) = serve_example()?;
+    let cache = tempdir::TempDir::new("cache")?;
+    let cache = Some(cache.path().to_string_lossy().to_string());
+    let load = || -> Result<Stats> {
+        let mut manager = Manager::new(&json, vec![], &cache)?;
+        let dependency = manager.resolve_id("example_main", "marvin@0.0.1")?;
+        assert_eq!(manager.load(dependency)?[0].1, b"\0asm");
+        Ok(manager.stats())
+    };
+
+    assert_eq!(load()?.transfers.len(), 1);
+    let blobs = std::path::Path::new(cache.as_ref().unwrap()).join("blobs/sha256");
+    for blob in std::fs::read_dir(blobs)? {
+        std::fs::write(blob?.path(), b"\0as")?;
+    }
+
+    // The corrupted blob is downloaded again and rewritten, so the load after that
+    // is served from the cache.
+    assert_eq!(load()?.transfers.len(), 1);
+    assert_eq!(load()?.transfers.len(), 0);
 
     Ok(())
 }
@@ -0,0 +1,199 @@
+use crate::compression::Encoding;
+use anyhow::Result;
+use serde_derive::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// The metadata document of a module, as served by a registry.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Metadata {
+    pub id: String,
+    // Attributes a platform must have to run the module.
+    #[serde(default)]
+    pub attributes: Vec<String>,
+    pub location: String,
+    #[serde(default)]
+    pub dependencies: HashMap<String, Dependency>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub registries: HashMap<String, String>,
+    // Used when the id itself carries no version.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    // Expected digest of the binary, e.g. "sha256:9f86d0...".
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub digest: Option<String>,
+    // Downloaded instead of `location` when present.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compressed: Option<Artifact>,
+    // Only used together with `digest`, which the patched binary must match.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub patches: Vec<Patch>,
+    // Imported symbols the module can run without, in addition to the weak imports
+    // of its dylink section.
+    #[serde(
+        default,
+        rename = "weakImports",
+        alias = "weak_imports",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub weak_imports: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Dependency {
+    pub id: String,
+    #[serde(rename = "loadTime", alias = "load_time")]
+    pub load_time: bool,
+    pub location: String,
+}
+
+impl PartialEq for Dependency {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id && self.load_time == other.load_time && self.location == other.location
+    }
+}
+
+/// A pre-compressed copy of a module binary, e.g. `side.wasm.zst`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Artifact {
+    pub location: String,
+    // Derived from the extension of `location` when missing.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encoding: Option<Encoding>,
+}
+
+/// A binary diff producing the module binary from an earlier binary with digest `from`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Patch {
+    pub from: String,
+    pub location: String,
+    // Derived from the extension of `location` when missing.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encoding: Option<Encoding>,
+}
+
+/// Answer of a registry's `/api/closure/:id`: the metadata selected for a module and for
+/// every module it transitively needs at load time, each with the digest of its binary.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Closure {
+    pub root: String,
+    pub modules: Vec<Metadata>,
+}
+
+/// Entry of a registry's `/api/modules` listing.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ModuleSummary {
+    pub id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    pub attributes: Vec<String>,
+    // Size of the binary in bytes, when the registry serves it itself.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    pub location: String,
+}
+
+pub(crate) fn artifact_encoding(location: &str, encoding: Option<Encoding>) -> Encoding {
+    encoding.unwrap_or_else(|| Encoding::from_location(location))
+}
+
+impl Metadata {
+    pub fn new(json: String) -> Result<Self> {
+        let metadata: Metadata = serde_json::from_str(&json)?;
+
+        Ok(metadata)
+    }
+
+    /// Whether a platform with `attributes` has every attribute the module requires.
+    pub fn supports(&self, attributes: &[String]) -> bool {
+        self.attributes.iter().all(|a| attributes.contains(a))
+    }
+}
+
+/// Picks the metadata of `id` that best fits a platform with `attributes`.
+///
+/// Metadata requiring attributes the platform lacks is discarded, and from the rest the
+/// one with most attributes, i.e. the most specific one, wins. Without `attributes`
+/// any metadata matches. Ties go to the earliest candidate.
+pub fn select<'a, I>(candidates: I, id: &str, attributes: Option<&[String]>) -> Option<&'a Metadata>
+where
+    I: IntoIterator<Item = &'a Metadata>,
+{
+    let mut best: Option<&Metadata> = None;
+    for metadata in candidates {
+        if metadata.id != id {
+            continue;
+        }
+        if let Some(attributes) = attributes {
+            if !metadata.supports(attributes) {
+                continue;
+            }
+        }
+        match best {
+            Some(best) if best.attributes.len() >= metadata.attributes.len() => {}
+            _ => best = Some(metadata),
+        }
+    }
+
+    best
+}
+
+#[cfg(test)]
+fn metadata(id: &str, attributes: &[&str]) -> Metadata {
+    Metadata {
+        id: id.to_string(),
+        attributes: attributes.iter().map(|a| a.to_string()).collect(),
+        location: format!("http://localhost:3000/{}.wasm", id),
+        dependencies: HashMap::new(),
+        registries: HashMap::new(),
+        version: None,
+        digest: None,
+        compressed: None,
+        patches: vec![],
+        weak_imports: vec![],
+    }
+}
+
+#[test]
+fn select_most_specific_supported_metadata() {
+    let candidates = vec![
+        metadata("marvin", &["aarch64"]),
+        metadata("marvin", &["aarch64", "Speaker"]),
+        metadata("marvin", &["aarch64", "Camera"]),
+        metadata("other", &[]),
+    ];
+    let attributes = |a: &[&str]| a.iter().map(|a| a.to_string()).collect::<Vec<_>>();
+
+    let camera = attributes(&["aarch64", "Mouse", "Camera"]);
+    let selected = select(&candidates, "marvin", Some(&camera)).unwrap();
+    assert_eq!(selected.attributes, vec!["aarch64", "Camera"]);
+
+    let plain = attributes(&["aarch64"]);
+    let selected = select(&candidates, "marvin", Some(&plain)).unwrap();
+    assert_eq!(selected.attributes, vec!["aarch64"]);
+
+    let selected = select(&candidates, "marvin", None).unwrap();
+    assert_eq!(selected.attributes, vec!["aarch64", "Speaker"]);
+
+    assert!(select(&candidates, "marvin", Some(&attributes(&["x86_64"]))).is_none());
+}
+
+#[test]
+fn metadata_round_trips_through_json() -> Result<()> {
+    let json = r#"{
+        "id": "side",
+        "attributes": [],
+        "location": "http://localhost:3000/dynamic_linking/side.wasm",
+        "dependencies": {
+            "main": { "location": "http://localhost:3000/api", "id": "main", "loadTime": false }
+        },
+        "weakImports": ["optional"]
+    }"#;
+    let metadata = Metadata::new(json.to_string())?;
+    let again = Metadata::new(serde_json::to_string(&metadata)?)?;
+
+    assert_eq!(again.dependencies["main"], metadata.dependencies["main"]);
+    assert_eq!(again.weak_imports, vec!["optional"]);
+    assert!(!serde_json::to_string(&metadata)?.contains("digest"));
+
+    Ok(())
+}
@@ -0,0 +1,72 @@
+use crate::metadata::Metadata;
+use anyhow::{anyhow, Result};
+use reqwest::header::AUTHORIZATION;
+use serde_derive::{Deserialize, Serialize};
+
+/// Body of a registry's `/api/publish`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PublishRequest {
+    pub metadata: Metadata,
+    // Base64 encoded module binary.
+    pub binary: String,
+}
+
+/// Answer of a successful publish: the metadata as stored by the registry,
+/// with its final `location` and `digest`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PublishResponse {
+    pub metadata: Metadata,
+    // Problems that did not prevent publishing, e.g. runtime dependencies the
+    // registry does not know yet.
+    #[serde(default)]
+    pub warnings: Vec<String>,
+}
+
+impl PublishRequest {
+    pub fn new(metadata: &Metadata, binary: &[u8]) -> Self {
+        Self {
+            metadata: metadata.clone(),
+            binary: base64::encode(binary),
+        }
+    }
+
+    pub fn binary(&self) -> Result<Vec<u8>> {
+        Ok(base64::decode(&self.binary)?)
+    }
+}
+
+/// Publishes `binary` with `metadata` to the registry at `location`, e.g.
+/// `http://localhost:3000/api`, authenticating with `token`. The registry
+/// validates both and computes the digest itself.
+pub fn publish(
+    location: &str,
+    token: &str,
+    metadata: &Metadata,
+    binary: &[u8],
+) -> Result<PublishResponse> {
+    let client = reqwest::blocking::Client::new();
+    let url = format!("{}/publish", location);
+    info!("publishing {} to {}", metadata.id, url);
+    let response = client
+        .post(&url)
+        .header(AUTHORIZATION, format!("Bearer {}", token))
+        .json(&PublishRequest::new(metadata, binary))
+        .send()?;
+
+    if !response.status().is_success() {
+        return Err(anyhow!(
+            "Publishing {} to {} failed with {}: {}",
+            metadata.id,
+            url,
+            response.status(),
+            response.text()?
+        ));
+    }
+
+    let response: PublishResponse = response.json()?;
+    for warning in &response.warnings {
+        warn!("{}", warning);
+    }
+
+    Ok(response)
+}
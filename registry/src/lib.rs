@@ -0,0 +1,1014 @@
+#[macro_use]
+extern crate log;
+
+mod validate;
+
+use anyhow::{anyhow, Result};
+use manager::{Closure, Metadata, ModuleId, ModuleSummary, PublishRequest, PublishResponse};
+use serde_derive::Deserialize;
+use std::fs;
+use std::io::{Cursor, Read};
+use std::net::SocketAddr;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use tiny_http::{Header, Request, Response, Server};
+
+type HttpResponse = Response<Cursor<Vec<u8>>>;
+
+// Upper bound of a publish request body, which holds the base64 encoded binary.
+const MAX_PUBLISH_SIZE: u64 = 64 * 1024 * 1024;
+
+/// Serves a `public/`-style tree of module binaries and metadata documents.
+///
+/// Every file is reachable through its path relative to the root, and
+/// `/api/:id` selects the metadata of `id` that best fits the attributes
+/// posted by the client, see `manager::select`. `/api/closure/:id` does the
+/// same for `id` and everything it needs at load time. `/api/publish`
+/// validates and stores new modules for clients holding the registry's token.
+/// `GET /api/modules` lists what is published.
+pub struct Registry {
+    root: PathBuf,
+    token: Option<String>,
+    // URL clients reach the registry at, which published metadata refers to.
+    public_url: Option<String>,
+}
+
+/// Why a module could not be published.
+#[derive(Debug)]
+pub enum PublishError {
+    /// The binary or its metadata is malformed or refers to unknown modules.
+    Invalid(String),
+    /// Different metadata with the same id and attributes was published before.
+    Conflict(String),
+    Internal(anyhow::Error),
+}
+
+impl std::fmt::Display for PublishError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PublishError::Invalid(message) | PublishError::Conflict(message) => {
+                write!(f, "{}", message)
+            }
+            PublishError::Internal(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for PublishError {}
+
+impl From<anyhow::Error> for PublishError {
+    fn from(e: anyhow::Error) -> Self {
+        PublishError::Internal(e)
+    }
+}
+
+impl From<std::io::Error> for PublishError {
+    fn from(e: std::io::Error) -> Self {
+        PublishError::Internal(e.into())
+    }
+}
+
+/// Body of a request to `/api/:id`. Leaving the attributes out matches any metadata.
+#[derive(Deserialize, Debug, Default)]
+struct Query {
+    attributes: Option<Vec<String>>,
+}
+
+/// A registry serving requests on a background thread until dropped.
+pub struct RunningRegistry {
+    address: SocketAddr,
+    server: Arc<Server>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl RunningRegistry {
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+}
+
+impl Drop for RunningRegistry {
+    fn drop(&mut self) {
+        self.server.unblock();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn percent_decode(segment: &str) -> Option<String> {
+    let bytes = segment.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        if bytes[index] == b'%' {
+            let hex = std::str::from_utf8(bytes.get(index + 1..index + 3)?).ok()?;
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            index += 3;
+        } else {
+            decoded.push(bytes[index]);
+            index += 1;
+        }
+    }
+
+    String::from_utf8(decoded).ok()
+}
+
+/// Parameters of the query string of `url`, e.g. `[("attribute", "Camera")]` for `/a?attribute=Camera`.
+fn query_parameters(url: &str) -> Option<Vec<(String, String)>> {
+    let query = match url.split_once('?') {
+        Some((_, query)) => query,
+        None => return Some(vec![]),
+    };
+
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            Some((
+                percent_decode(&name.replace('+', " "))?,
+                percent_decode(&value.replace('+', " "))?,
+            ))
+        })
+        .collect()
+}
+
+fn content_type(path: &Path) -> &'static str {
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("wasm") => "application/wasm",
+        Some("json") => "application/json",
+        Some("html") => "text/html; charset=utf-8",
+        Some("c") | Some("h") | Some("sh") | Some("txt") => "text/plain; charset=utf-8",
+        _ => "application/octet-stream",
+    }
+}
+
+fn header(name: &str, value: &str) -> Header {
+    Header::from_bytes(name.as_bytes(), value.as_bytes()).unwrap()
+}
+
+fn status(code: u16, message: &str) -> HttpResponse {
+    Response::from_string(message).with_status_code(code)
+}
+
+fn json(value: &impl serde::Serialize) -> HttpResponse {
+    match serde_json::to_vec(value) {
+        Ok(body) => Response::from_data(body)
+            .with_header(header("Content-Type", "application/json; charset=utf-8")),
+        Err(e) => status(500, &e.to_string()),
+    }
+}
+
+impl Registry {
+    pub fn new<P: AsRef<Path>>(root: P) -> Self {
+        Self {
+            root: root.as_ref().to_path_buf(),
+            token: None,
+            public_url: None,
+        }
+    }
+
+    /// Sets the URL other devices reach the registry at, e.g. `http://registry.local:3000`.
+    /// Published binaries are served from below it, and publishing is disabled without it.
+    pub fn with_public_url(mut self, url: &str) -> Self {
+        self.public_url = Some(url.trim_end_matches('/').to_string());
+        self
+    }
+
+    /// Enables publishing for clients that send `token` as bearer token.
+    pub fn with_token(mut self, token: &str) -> Self {
+        self.token = Some(token.to_string());
+        self
+    }
+
+    /// Every metadata document below the root, in path order. Json files that are
+    /// not valid metadata are skipped.
+    pub fn metadata(&self) -> Result<Vec<Metadata>> {
+        fn collect(directory: &Path, paths: &mut Vec<PathBuf>) -> Result<()> {
+            let mut entries = fs::read_dir(directory)?
+                .map(|entry| entry.map(|e| e.path()))
+                .collect::<std::io::Result<Vec<_>>>()?;
+            entries.sort();
+            for path in entries {
+                if path.is_dir() {
+                    collect(&path, paths)?;
+                } else if path.extension().map(|e| e == "json").unwrap_or(false) {
+                    paths.push(path);
+                }
+            }
+            Ok(())
+        }
+
+        let mut paths = vec![];
+        collect(&self.root, &mut paths)?;
+
+        let mut metadata = vec![];
+        for path in paths {
+            match serde_json::from_slice::<Metadata>(&fs::read(&path)?) {
+                Ok(m) => metadata.push(m),
+                Err(e) => warn!(
+                    "Skipping {}, it is not module metadata: {}",
+                    path.display(),
+                    e
+                ),
+            }
+        }
+
+        Ok(metadata)
+    }
+
+    /// The metadata of `id` that best fits a platform with `attributes`.
+    pub fn select(&self, id: &str, attributes: Option<&[String]>) -> Result<Option<Metadata>> {
+        let metadata = self.metadata()?;
+
+        Ok(manager::select(&metadata, id, attributes).cloned())
+    }
+
+    pub fn handle(&self, request: &mut Request) -> HttpResponse {
+        let url = request.url().to_string();
+        let path = url.split('?').next().unwrap_or("");
+        info!("{} {}", request.method(), path);
+
+        if path == "/api/publish" {
+            return self.handle_publish(request);
+        }
+
+        if path == "/api/modules" && request.method() == &tiny_http::Method::Get {
+            return match query_parameters(&url) {
+                Some(parameters) => self.handle_list(&parameters),
+                None => status(400, "invalid query"),
+            };
+        }
+
+        if let Some(id) = path.strip_prefix("/api/closure/") {
+            return match percent_decode(id) {
+                Some(id) if !id.is_empty() => self.handle_closure(&id, request),
+                _ => status(400, "invalid module id"),
+            };
+        }
+
+        if let Some(id) = path.strip_prefix("/api/") {
+            return match percent_decode(id) {
+                Some(id) if !id.is_empty() => self.handle_select(&id, request),
+                _ => status(400, "invalid module id"),
+            };
+        }
+
+        match percent_decode(path) {
+            Some(path) => self.handle_file(&path),
+            None => status(400, "invalid path"),
+        }
+    }
+
+    /// Every published module that has all of `attributes` and whose id contains `search`,
+    /// ordered by id.
+    pub fn list(&self, attributes: &[String], search: Option<&str>) -> Result<Vec<ModuleSummary>> {
+        let mut modules: Vec<ModuleSummary> = self
+            .metadata()?
+            .into_iter()
+            .filter(|m| attributes.iter().all(|a| m.attributes.contains(a)))
+            .filter(|m| search.map(|s| m.id.contains(s)).unwrap_or(true))
+            .map(|m| ModuleSummary {
+                version: m
+                    .version
+                    .clone()
+                    .or_else(|| ModuleId::parse(&m.id).ok().and_then(|id| id.version)),
+                size: self
+                    .local_file(&m.location)
+                    .and_then(|path| fs::metadata(path).ok())
+                    .map(|file| file.len()),
+                id: m.id,
+                attributes: m.attributes,
+                location: m.location,
+            })
+            .collect();
+        // Stable, so metadata of one id keeps its path order.
+        modules.sort_by(|a, b| a.id.cmp(&b.id));
+
+        Ok(modules)
+    }
+
+    /// The selected metadata of `id` and of all its load-time dependencies, in the order
+    /// the manager discovers them. Metadata without a digest gets the digest of its binary
+    /// when the binary is served from this registry.
+    pub fn resolve(&self, id: &str, attributes: Option<&[String]>) -> Result<Option<Closure>> {
+        let candidates = self.metadata()?;
+        let root = match manager::select(&candidates, id, attributes) {
+            Some(root) => root.clone(),
+            None => return Ok(None),
+        };
+
+        let mut modules = vec![root];
+        let mut index = 0;
+        while index < modules.len() {
+            let mut dependencies: Vec<_> = modules[index]
+                .dependencies
+                .values()
+                .filter(|d| d.load_time)
+                .map(|d| d.id.clone())
+                .collect();
+            dependencies.sort();
+
+            for dependency in dependencies {
+                if modules.iter().any(|m| m.id == dependency) {
+                    continue;
+                }
+                let metadata =
+                    manager::select(&candidates, &dependency, attributes).ok_or(anyhow!(
+                        "no match for {}, a load-time dependency of {}",
+                        dependency,
+                        modules[index].id
+                    ))?;
+                modules.push(metadata.clone());
+            }
+            index += 1;
+        }
+
+        for metadata in modules.iter_mut() {
+            if metadata.digest.is_none() {
+                metadata.digest = self.local_digest(&metadata.location);
+            }
+        }
+
+        Ok(Some(Closure {
+            root: id.to_string(),
+            modules,
+        }))
+    }
+
+    /// Validates `binary` and its `metadata` and stores both below the root, the binary
+    /// being served from below the public URL. Publishing an identical module again is a no-op.
+    pub fn publish(
+        &self,
+        mut metadata: Metadata,
+        binary: &[u8],
+    ) -> std::result::Result<PublishResponse, PublishError> {
+        let invalid = |e: anyhow::Error| PublishError::Invalid(e.to_string());
+        let public_url = self
+            .public_url
+            .as_ref()
+            .ok_or_else(|| anyhow!("the registry has no public URL to publish modules under"))?;
+
+        let id = ModuleId::parse(&metadata.id).map_err(invalid)?;
+        let analysis = validate::analyse(binary).map_err(invalid)?;
+        validate::check_dependencies(&metadata, &analysis).map_err(invalid)?;
+
+        let digest = manager::digest(binary);
+        if let Some(declared) = &metadata.digest {
+            if declared != &digest {
+                return Err(PublishError::Invalid(format!(
+                    "the binary hashes to {}, not to the declared {}",
+                    digest, declared
+                )));
+            }
+        }
+        metadata.digest = Some(digest.clone());
+
+        let published = self.metadata()?;
+        let mut warnings = vec![];
+        for dependency in metadata.dependencies.values() {
+            if dependency.id == metadata.id || published.iter().any(|m| m.id == dependency.id) {
+                continue;
+            }
+            // Modules of other registries cannot be checked from here.
+            let location = ModuleId::parse(&dependency.id)
+                .ok()
+                .and_then(|id| id.namespace)
+                .and_then(|namespace| metadata.registries.get(&namespace))
+                .unwrap_or(&dependency.location);
+            let local = location.is_empty()
+                || location == public_url
+                || location.starts_with(&format!("{}/", public_url));
+            if !local {
+                warnings.push(format!(
+                    "dependency {} on {} is not checked",
+                    dependency.id, location
+                ));
+                continue;
+            }
+            return Err(PublishError::Invalid(format!(
+                "dependency {} does not exist",
+                dependency.id
+            )));
+        }
+
+        let mut attributes = metadata.attributes.clone();
+        attributes.sort();
+        for existing in published.iter().filter(|m| m.id == metadata.id) {
+            let mut existing_attributes = existing.attributes.clone();
+            existing_attributes.sort();
+            if existing_attributes != attributes {
+                continue;
+            }
+            let existing_digest = existing
+                .digest
+                .clone()
+                .or_else(|| self.local_digest(&existing.location));
+            if existing_digest.as_deref() == Some(digest.as_str()) {
+                return Ok(PublishResponse {
+                    metadata: existing.clone(),
+                    warnings,
+                });
+            }
+            return Err(PublishError::Conflict(format!(
+                "{} with attributes {:?} is already published with a different binary",
+                metadata.id, metadata.attributes
+            )));
+        }
+
+        // Binaries are named after their digest, so different builds of one id never collide.
+        let directory = id
+            .namespace
+            .clone()
+            .unwrap_or_else(|| "published".to_string());
+        let stem = match &id.version {
+            Some(version) => format!("{}@{}", id.name, version),
+            None => id.name.clone(),
+        };
+        let hex = digest.split(':').nth(1).unwrap_or(&digest);
+        let stem = format!("{}-{}", stem, &hex[..12.min(hex.len())]);
+        let relative = format!("{}/{}.wasm", directory, stem);
+        // Ids are checked when parsed, but nothing is written unless it stays under the root.
+        let stored = |file: &str| {
+            self.local_path(file)
+                .filter(|path| path.starts_with(&self.root))
+                .ok_or_else(|| {
+                    PublishError::Invalid(format!(
+                        "{} cannot be stored in the registry",
+                        metadata.id
+                    ))
+                })
+        };
+        let binary_path = stored(&relative)?;
+        let metadata_path = stored(&format!("{}/{}.json", directory, stem))?;
+
+        fs::create_dir_all(self.root.join(&directory))?;
+        fs::write(binary_path, binary)?;
+        metadata.location = format!("{}/{}", public_url, relative);
+        // The metadata is written last, so it never refers to a missing binary.
+        fs::write(
+            metadata_path,
+            serde_json::to_vec_pretty(&metadata).map_err(anyhow::Error::from)?,
+        )?;
+        info!("Published {} as {}", metadata.id, relative);
+
+        Ok(PublishResponse { metadata, warnings })
+    }
+
+    /// Path of the file a location URL refers to, if it is served from the root.
+    fn local_file(&self, location: &str) -> Option<PathBuf> {
+        let (_, path) = location.split_once("://")?;
+        let path = percent_decode(&path[path.find('/')?..])?;
+
+        self.local_path(&path)
+    }
+
+    /// Digest of the file a location URL refers to, if it is served from the root.
+    fn local_digest(&self, location: &str) -> Option<String> {
+        fs::read(self.local_file(location)?)
+            .ok()
+            .map(|binary| manager::digest(&binary))
+    }
+
+    fn local_path(&self, path: &str) -> Option<PathBuf> {
+        let relative = Path::new(path.trim_start_matches('/'));
+        if relative
+            .components()
+            .any(|c| !matches!(c, Component::Normal(_)))
+        {
+            return None;
+        }
+
+        Some(self.root.join(relative))
+    }
+
+    fn read_query(request: &mut Request) -> std::result::Result<Query, HttpResponse> {
+        let mut body = vec![];
+        if let Err(e) = request.as_reader().read_to_end(&mut body) {
+            return Err(status(400, &e.to_string()));
+        }
+
+        if body.iter().all(|b| b.is_ascii_whitespace()) {
+            Ok(Query::default())
+        } else {
+            serde_json::from_slice::<Query>(&body).map_err(|e| status(400, &e.to_string()))
+        }
+    }
+
+    fn handle_closure(&self, id: &str, request: &mut Request) -> HttpResponse {
+        let query = match Registry::read_query(request) {
+            Ok(query) => query,
+            Err(response) => return response,
+        };
+
+        // A 404 tells clients that the endpoint does not exist, so modules
+        // that cannot be resolved are reported as unprocessable instead.
+        match self.resolve(id, query.attributes.as_deref()) {
+            Ok(Some(closure)) => json(&closure),
+            Ok(None) => status(422, "no match"),
+            Err(e) => status(422, &e.to_string()),
+        }
+    }
+
+    fn handle_select(&self, id: &str, request: &mut Request) -> HttpResponse {
+        let query = match Registry::read_query(request) {
+            Ok(query) => query,
+            Err(response) => return response,
+        };
+
+        match self.select(id, query.attributes.as_deref()) {
+            Ok(Some(metadata)) => json(&metadata),
+            Ok(None) => status(404, "no match"),
+            Err(e) => {
+                error!("{}", e);
+                status(500, &e.to_string())
+            }
+        }
+    }
+
+    fn handle_list(&self, parameters: &[(String, String)]) -> HttpResponse {
+        let attributes: Vec<String> = parameters
+            .iter()
+            .filter(|(name, _)| name == "attribute")
+            .map(|(_, value)| value.clone())
+            .collect();
+        let search = parameters
+            .iter()
+            .find(|(name, _)| name == "q")
+            .map(|(_, value)| value.as_str());
+
+        match self.list(&attributes, search) {
+            Ok(modules) => json(&modules),
+            Err(e) => {
+                error!("{}", e);
+                status(500, &e.to_string())
+            }
+        }
+    }
+
+    fn handle_publish(&self, request: &mut Request) -> HttpResponse {
+        if request.method() != &tiny_http::Method::Post {
+            return status(405, "publishing requires POST");
+        }
+        let token = match (&self.token, &self.public_url) {
+            (Some(token), Some(_)) => token,
+            _ => return status(403, "publishing is disabled on this registry"),
+        };
+        let authorization = request
+            .headers()
+            .iter()
+            .find(|h| h.field.equiv("Authorization"))
+            .map(|h| h.value.as_str().to_string());
+        if authorization.as_deref() != Some(format!("Bearer {}", token).as_str()) {
+            return status(401, "invalid token");
+        }
+
+        let mut body = vec![];
+        if let Err(e) = request
+            .as_reader()
+            .take(MAX_PUBLISH_SIZE + 1)
+            .read_to_end(&mut body)
+        {
+            return status(400, &e.to_string());
+        }
+        if body.len() as u64 > MAX_PUBLISH_SIZE {
+            return status(413, "module is too large");
+        }
+        let (publish, binary) = match serde_json::from_slice::<PublishRequest>(&body)
+            .map_err(anyhow::Error::from)
+            .and_then(|publish| Ok((publish.binary()?, publish)))
+        {
+            Ok((binary, publish)) => (publish, binary),
+            Err(e) => return status(400, &e.to_string()),
+        };
+
+        match self.publish(publish.metadata, &binary) {
+            Ok(response) => json(&response),
+            Err(PublishError::Invalid(message)) => status(400, &message),
+            Err(PublishError::Conflict(message)) => status(409, &message),
+            Err(PublishError::Internal(e)) => {
+                error!("{}", e);
+                status(500, &e.to_string())
+            }
+        }
+    }
+
+    fn handle_file(&self, path: &str) -> HttpResponse {
+        let full_path = match self.local_path(path) {
+            Some(full_path) => full_path,
+            None => return status(404, "not found"),
+        };
+        if full_path.is_dir() {
+            return self.directory_listing(path, &full_path);
+        }
+
+        match fs::read(&full_path) {
+            Ok(data) => Response::from_data(data)
+                .with_header(header("Content-Type", content_type(&full_path))),
+            Err(_) => status(404, "not found"),
+        }
+    }
+
+    fn directory_listing(&self, path: &str, directory: &Path) -> HttpResponse {
+        let mut names = match fs::read_dir(directory) {
+            Ok(entries) => entries
+                .filter_map(|e| e.ok())
+                .map(|e| {
+                    let name = e.file_name().to_string_lossy().to_string();
+                    if e.path().is_dir() {
+                        format!("{}/", name)
+                    } else {
+                        name
+                    }
+                })
+                .collect::<Vec<_>>(),
+            Err(e) => return status(500, &e.to_string()),
+        };
+        names.sort();
+
+        let base = format!("{}/", path.trim_end_matches('/'));
+        let items: String = names
+            .iter()
+            .map(|name| format!("<li><a href=\"{}{}\">{}</a></li>\n", base, name, name))
+            .collect();
+        let page = format!(
+            "<!DOCTYPE html>\n<html><head><title>{0}</title></head>\n<body><h1>{0}</h1><ul>\n{1}</ul></body></html>\n",
+            base, items
+        );
+
+        Response::from_string(page).with_header(header(
+            "Content-Type",
+            content_type(Path::new("index.html")),
+        ))
+    }
+
+    pub fn serve(&self, server: &Server) {
+        for mut request in server.incoming_requests() {
+            let response = self.handle(&mut request);
+            if let Err(e) = request.respond(response) {
+                warn!("Failed to respond: {}", e);
+            }
+        }
+    }
+
+    /// Starts serving on `address` in a background thread.
+    pub fn spawn(self, address: &str) -> Result<RunningRegistry> {
+        let server = Arc::new(Server::http(address).map_err(|e| anyhow!("{}", e))?);
+        let address = server
+            .server_addr()
+            .to_ip()
+            .ok_or(anyhow!("{} is not an IP address", address))?;
+        let thread = {
+            let server = server.clone();
+            std::thread::spawn(move || self.serve(&server))
+        };
+
+        Ok(RunningRegistry {
+            address,
+            server,
+            thread: Some(thread),
+        })
+    }
+}
+
+#[cfg(test)]
+fn write_module(root: &Path, id: &str, attributes: &[&str], dependencies: &str, location: &str) {
+    let metadata = format!(
+        r#"{{ "id": "{}", "attributes": {:?}, "location": "{}", "dependencies": {{ {} }} }}"#,
+        id, attributes, location, dependencies
+    );
+    let file = format!("{}_{}.json", id.replace('/', "_"), attributes.join("_"));
+    fs::write(root.join(file), metadata).unwrap();
+}
+
+#[test]
+fn api_selects_best_match_for_attributes() -> Result<()> {
+    let root = tempdir::TempDir::new("public")?;
+    write_module(root.path(), "marvin@1.0.0", &["aarch64"], "", "a");
+    write_module(root.path(), "marvin@1.0.0", &["aarch64", "Camera"], "", "b");
+    write_module(
+        root.path(),
+        "marvin@1.0.0",
+        &["aarch64", "Speaker"],
+        "",
+        "c",
+    );
+    fs::write(root.path().join("notes.json"), "[]")?;
+
+    let registry = Registry::new(root.path()).spawn("127.0.0.1:0")?;
+    let url = format!("http://{}/api/marvin@1.0.0", registry.address());
+    let client = reqwest::blocking::Client::new();
+
+    let query = serde_json::json!({ "attributes": ["aarch64", "Camera", "Mouse"] });
+    let selected: Metadata = client.post(&url).json(&query).send()?.json()?;
+    assert_eq!(selected.location, "b");
+
+    let query = serde_json::json!({ "attributes": ["x86_64"] });
+    assert_eq!(client.post(&url).json(&query).send()?.status(), 404);
+
+    // Leaving the body out matches any metadata.
+    assert!(client.post(&url).send()?.status().is_success());
+
+    Ok(())
+}
+
+#[test]
+fn manager_loads_modules_through_registry() -> Result<()> {
+    let root = tempdir::TempDir::new("public")?;
+    let cache = tempdir::TempDir::new("cache")?;
+    let registry = Registry::new(root.path()).spawn("127.0.0.1:0")?;
+    let base = format!("http://{}", registry.address());
+
+    fs::create_dir(root.path().join("acme"))?;
+    fs::write(root.path().join("acme/imaging.wasm"), b"\0asm\x01\0\0\0")?;
+    write_module(
+        &root.path().join("acme"),
+        "acme/imaging@1.2",
+        &["android"],
+        "",
+        &format!("{}/acme/imaging.wasm", base),
+    );
+
+    let main = format!(
+        r#"{{ "id": "main", "attributes": [], "location": "{0}/main.wasm", "dependencies": {{
+            "acme/imaging@1.2": {{ "id": "acme/imaging@1.2", "loadTime": false, "location": "{0}/api" }}
+        }} }}"#,
+        base
+    );
+    let mut manager = manager::Manager::new(
+        &main,
+        vec!["android".to_string()],
+        &Some(cache.path().to_str().unwrap().to_string()),
+    )?;
+
+    let dependency = manager.resolve_id("main", "acme/imaging")?;
+    let binaries = manager.load(dependency)?;
+    assert_eq!(binaries.len(), 1);
+    assert_eq!(binaries[0].1, b"\0asm\x01\0\0\0");
+
+    Ok(())
+}
+
+#[test]
+fn closure_contains_load_time_dependencies_with_digests() -> Result<()> {
+    let root = tempdir::TempDir::new("public")?;
+    let registry = Registry::new(root.path()).spawn("127.0.0.1:0")?;
+    let base = format!("http://{}", registry.address());
+    let dependency = |id: &str, load_time: bool| {
+        format!(
+            r#""{0}": {{ "id": "{0}", "loadTime": {1}, "location": "{2}/api" }}"#,
+            id, load_time, base
+        )
+    };
+
+    for id in &["app", "a", "b", "lazy"] {
+        fs::write(root.path().join(format!("{}.wasm", id)), id.as_bytes())?;
+    }
+    let location = |id: &str| format!("{}/{}.wasm", base, id);
+    write_module(
+        root.path(),
+        "app",
+        &[],
+        &format!(
+            "{}, {}, {}",
+            dependency("a", true),
+            dependency("b", true),
+            dependency("lazy", false)
+        ),
+        &location("app"),
+    );
+    write_module(
+        root.path(),
+        "a",
+        &[],
+        &dependency("b", true),
+        &location("a"),
+    );
+    write_module(root.path(), "b", &[], "", &location("b"));
+    write_module(root.path(), "lazy", &[], "", &location("lazy"));
+
+    let closure = Registry::new(root.path()).resolve("app", None)?.unwrap();
+    let ids: Vec<_> = closure.modules.iter().map(|m| m.id.as_str()).collect();
+    assert_eq!(ids, vec!["app", "a", "b"]);
+    assert_eq!(closure.modules[2].digest, Some(manager::digest(b"b")));
+
+    let client = reqwest::blocking::Client::new();
+    let response = client
+        .post(&format!("{}/api/closure/missing", base))
+        .send()?;
+    assert_eq!(response.status(), 422);
+
+    let cache = tempdir::TempDir::new("cache")?;
+    let main = format!(
+        r#"{{ "id": "main", "attributes": [], "location": "{}/main.wasm", "dependencies": {{ {} }} }}"#,
+        base,
+        dependency("app", false)
+    );
+    let mut manager = manager::Manager::new(
+        &main,
+        vec![],
+        &Some(cache.path().to_str().unwrap().to_string()),
+    )?;
+    let app = manager.resolve_id("main", "app")?;
+    let binaries = manager.load(app)?;
+    let mut loaded: Vec<_> = binaries
+        .iter()
+        .map(|(d, binary)| (d.id.as_str(), binary.as_slice()))
+        .collect();
+    // The requested module comes first, the order of the rest follows the metadata maps.
+    loaded[1..].sort();
+    assert_eq!(
+        loaded,
+        vec![("app", &b"app"[..]), ("a", &b"a"[..]), ("b", &b"b"[..])]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn modules_are_listed_and_filtered() -> Result<()> {
+    let root = tempdir::TempDir::new("public")?;
+    let registry = Registry::new(root.path()).spawn("127.0.0.1:0")?;
+    let base = format!("http://{}", registry.address());
+    fs::write(root.path().join("invert.wasm"), b"\0asm\x01\0\0\0")?;
+    write_module(
+        root.path(),
+        "android_bmp_invert@1.0",
+        &["android"],
+        "",
+        &format!("{}/invert.wasm", base),
+    );
+    write_module(
+        root.path(),
+        "android_bmp_grayscale",
+        &["android", "Camera"],
+        "",
+        "elsewhere",
+    );
+    write_module(root.path(), "marvin@1.0.0", &["aarch64"], "", "marvin");
+
+    let modules: Vec<ModuleSummary> =
+        reqwest::blocking::get(&format!("{}/api/modules", base))?.json()?;
+    let ids: Vec<_> = modules.iter().map(|m| m.id.as_str()).collect();
+    assert_eq!(
+        ids,
+        vec![
+            "android_bmp_grayscale",
+            "android_bmp_invert@1.0",
+            "marvin@1.0.0"
+        ]
+    );
+    assert_eq!(modules[1].version.as_deref(), Some("1.0"));
+    assert_eq!(modules[1].size, Some(8));
+    assert_eq!(modules[0].size, None);
+
+    let url = format!("{}/api/modules?attribute=android&q=bmp%5Fg", base);
+    let modules: Vec<ModuleSummary> = reqwest::blocking::get(&url)?.json()?;
+    assert_eq!(modules.len(), 1);
+    assert_eq!(modules[0].id, "android_bmp_grayscale");
+
+    // The manager only offers modules this platform can run.
+    let cache = tempdir::TempDir::new("cache")?;
+    let main = r#"{ "id": "main", "attributes": [], "location": "main.wasm" }"#;
+    let manager = manager::Manager::new(
+        main,
+        vec!["android".to_string()],
+        &Some(cache.path().to_str().unwrap().to_string()),
+    )?;
+    let modules = manager.list_modules(&format!("{}/api", base), &["android".to_string()])?;
+    let ids: Vec<_> = modules.iter().map(|m| m.id.as_str()).collect();
+    assert_eq!(ids, vec!["android_bmp_invert@1.0"]);
+
+    Ok(())
+}
+
+/// An address on the loopback interface that nothing listens on at the moment.
+#[cfg(test)]
+fn free_address() -> Result<SocketAddr> {
+    Ok(std::net::TcpListener::bind("127.0.0.1:0")?.local_addr()?)
+}
+
+#[test]
+fn publish_validates_and_stores_modules() -> Result<()> {
+    let side = include_bytes!("../../modules/public/dynamic_linking/side.wasm");
+    let root = tempdir::TempDir::new("public")?;
+    let address = free_address()?;
+    let public_url = format!("http://{}", address);
+    let registry = Registry::new(root.path())
+        .with_token("secret")
+        .with_public_url(&public_url)
+        .spawn(&address.to_string())?;
+    // Published through another name than the public one.
+    let api = format!("http://localhost:{}/api", registry.address().port());
+    write_module(root.path(), "main", &[], "", "unused");
+
+    let metadata: Metadata = serde_json::from_str(
+        r#"{ "id": "side", "attributes": [], "location": "", "dependencies": {
+            "main": { "id": "main", "loadTime": false, "location": "" }
+        } }"#,
+    )?;
+    assert!(manager::publish(&api, "wrong", &metadata, side).is_err());
+    assert!(manager::publish(&api, "secret", &metadata, b"\0asm\x01\0\0").is_err());
+
+    let published = manager::publish(&api, "secret", &metadata, side)?;
+    assert_eq!(published.metadata.digest, Some(manager::digest(side)));
+    assert!(published.warnings.is_empty());
+    assert!(published.metadata.location.starts_with(&public_url));
+    let served = reqwest::blocking::get(&published.metadata.location)?.bytes()?;
+    assert_eq!(&served[..], &side[..]);
+    let selected: Metadata = reqwest::blocking::Client::new()
+        .post(&format!("{}/side", api))
+        .send()?
+        .json()?;
+    assert_eq!(selected.location, published.metadata.location);
+
+    // Publishing the same binary again is accepted, a different one is a conflict.
+    assert_eq!(
+        manager::publish(&api, "secret", &metadata, side)?
+            .metadata
+            .location,
+        published.metadata.location
+    );
+    let mut changed = side.to_vec();
+    changed.extend_from_slice(b"\0\x01x");
+    assert!(manager::publish(&api, "secret", &metadata, &changed).is_err());
+
+    // Ids cannot place files outside of the root.
+    for id in &["../escape", "./escape", "escape/.."] {
+        let outside = Metadata {
+            id: id.to_string(),
+            ..metadata.clone()
+        };
+        assert!(manager::publish(&api, "secret", &outside, side).is_err());
+    }
+    let parent = root.path().parent().unwrap();
+    let mut siblings = fs::read_dir(parent)?.filter_map(|entry| entry.ok());
+    assert!(!siblings.any(|entry| entry.file_name().to_string_lossy().starts_with("escape")));
+
+    Ok(())
+}
+
+#[test]
+fn publish_rejects_missing_dependencies() -> Result<()> {
+    let side = include_bytes!("../../modules/public/dynamic_linking/side.wasm");
+    let root = tempdir::TempDir::new("public")?;
+    let registry = Registry::new(root.path()).with_public_url("http://localhost:3000");
+
+    let mut metadata: Metadata = serde_json::from_str(
+        r#"{ "id": "side", "attributes": [], "location": "", "dependencies": {
+            "main": { "id": "main", "loadTime": true, "location": "http://localhost:3000/api" }
+        } }"#,
+    )?;
+    for load_time in &[true, false] {
+        metadata
+            .dependencies
+            .values_mut()
+            .for_each(|d| d.load_time = *load_time);
+        match registry.publish(metadata.clone(), side) {
+            Err(PublishError::Invalid(message)) => assert!(message.contains("main")),
+            other => panic!("unexpected result {:?}", other.map(|r| r.metadata.id)),
+        }
+    }
+
+    // Modules of other registries are not checked, whether their own location or the
+    // registries of their namespace point there.
+    let dependencies = r#"{
+        "main": { "id": "main", "loadTime": true, "location": "http://localhost:3001/api" },
+        "acme/main": { "id": "acme/main", "loadTime": true, "location": "http://localhost:3000/api" }
+    }"#;
+    metadata.dependencies = serde_json::from_str(dependencies)?;
+    metadata.registries.insert(
+        "acme".to_string(),
+        "http://registry.acme.com/api".to_string(),
+    );
+    let published = registry.publish(metadata, side)?;
+    assert_eq!(published.warnings.len(), 2);
+
+    Ok(())
+}
+
+#[test]
+fn publishing_is_disabled_without_token() -> Result<()> {
+    let root = tempdir::TempDir::new("public")?;
+    let registry = Registry::new(root.path()).spawn("127.0.0.1:0")?;
+    let response = reqwest::blocking::Client::new()
+        .post(&format!("http://{}/api/publish", registry.address()))
+        .body("{}")
+        .send()?;
+    assert_eq!(response.status(), 403);
+
+    // A token alone is not enough, published metadata needs the public URL.
+    let registry = Registry::new(root.path())
+        .with_token("secret")
+        .spawn("127.0.0.1:0")?;
+    let response = reqwest::blocking::Client::new()
+        .post(&format!("http://{}/api/publish", registry.address()))
+        .bearer_auth("secret")
+        .body("{}")
+        .send()?;
+    assert_eq!(response.status(), 403);
+
+    Ok(())
+}
@@ -0,0 +1,25 @@
+use registry::Registry;
+use simplelog::{Config, LevelFilter, SimpleLogger};
+
+fn main() {
+    SimpleLogger::init(LevelFilter::Info, Config::default()).unwrap();
+
+    let args: Vec<String> = std::env::args().collect();
+    let root = args.get(1).map(|a| a.as_str()).unwrap_or("public");
+    let port = std::env::var("PORT").unwrap_or_else(|_| "3000".to_string());
+
+    let server = tiny_http::Server::http(format!("0.0.0.0:{}", port))
+        .unwrap_or_else(|e| panic!("Cannot listen on port {}: {}", port, e));
+    println!("Registry serving {} on port {}!", root, port);
+
+    let mut registry = Registry::new(root);
+    match std::env::var("REGISTRY_TOKEN") {
+        Ok(token) if !token.is_empty() => registry = registry.with_token(&token),
+        _ => println!("REGISTRY_TOKEN is not set, publishing is disabled"),
+    }
+    match std::env::var("REGISTRY_URL") {
+        Ok(url) if !url.is_empty() => registry = registry.with_public_url(&url),
+        _ => println!("REGISTRY_URL is not set, publishing is disabled"),
+    }
+    registry.serve(&server);
+}
@@ -0,0 +1,114 @@
+use anyhow::{anyhow, Result};
+use dylink::Dylink;
+use manager::Metadata;
+use wasmparser::{Parser, Payload, Validator, WasmFeatures};
+
+// Import modules the runtime provides itself. Every other import module
+// has to be the id of a dependency, which the runtime links by name.
+const HOST_MODULES: &[&str] = &[
+    "env",
+    "GOT.mem",
+    "GOT.func",
+    "host",
+    "wasi_snapshot_preview1",
+    "wasi_unstable",
+];
+
+/// What the registry learned about a binary while validating it.
+#[derive(Debug)]
+pub struct Analysis {
+    // Present for side modules, which start with a `dylink.0` or `dylink` section.
+    pub dylink: Option<Dylink>,
+    // (module, name) of every import.
+    pub imports: Vec<(String, String)>,
+}
+
+/// Validates `binary` the way the runtime will load it.
+pub fn analyse(binary: &[u8]) -> Result<Analysis> {
+    let features = WasmFeatures {
+        simd: true,
+        ..WasmFeatures::default()
+    };
+    Validator::new()
+        .wasm_features(features)
+        .validate_all(binary)
+        .map_err(|e| anyhow!("invalid WebAssembly module: {}", e))?;
+
+    let mut dylink = None;
+    let mut imports = vec![];
+    for payload in Parser::new(0).parse_all(binary) {
+        match payload? {
+            Payload::CustomSection { name: "dylink", .. }
+            | Payload::CustomSection {
+                name: "dylink.0", ..
+            } => dylink = Some(Dylink::check_dylink(binary)?),
+            Payload::ImportSection(reader) => {
+                for import in reader {
+                    let import = import?;
+                    imports.push((
+                        import.module.to_string(),
+                        import.field.unwrap_or("").to_string(),
+                    ));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    Ok(Analysis { dylink, imports })
+}
+
+/// Checks that the imports and needed libraries of a binary are covered by
+/// the dependencies declared in its `metadata`.
+pub fn check_dependencies(metadata: &Metadata, analysis: &Analysis) -> Result<()> {
+    let declared = |id: &str| {
+        metadata
+            .dependencies
+            .iter()
+            .any(|(key, dependency)| key == id || dependency.id == id)
+    };
+
+    for (module, name) in &analysis.imports {
+        if !HOST_MODULES.contains(&module.as_str()) && !declared(module) {
+            return Err(anyhow!(
+                "{} imports {} from {}, which is neither provided by the runtime nor a dependency",
+                metadata.id,
+                name,
+                module
+            ));
+        }
+    }
+
+    if let Some(dylink) = &analysis.dylink {
+        for library in &dylink.needed_dynlibs {
+            if !declared(manager::library_id(library)) {
+                return Err(anyhow!(
+                    "{} needs {}, which is not among its dependencies",
+                    metadata.id,
+                    library
+                ));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[test]
+fn side_module_is_analysed() -> Result<()> {
+    let side = include_bytes!("../../modules/public/dynamic_linking/side.wasm");
+    let analysis = analyse(side)?;
+    let dylink = analysis.dylink.unwrap();
+    assert!(dylink.needed_dynlibs.is_empty());
+    assert!(analysis.imports.iter().any(|(module, _)| module == "env"));
+
+    Ok(())
+}
+
+#[test]
+fn malformed_binaries_are_rejected() {
+    assert!(analyse(b"").is_err());
+    assert!(analyse(b"\0asm\x01\0\0\0\x01").is_err());
+    // A dylink section that is not the first section.
+    assert!(analyse(b"\0asm\x01\0\0\0\x01\x01\0\0\x07\x06dylink").is_err());
+}
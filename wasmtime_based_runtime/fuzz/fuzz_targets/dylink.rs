@@ -0,0 +1,13 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use dylink::Dylink;
+
+// Any input must either parse or produce a `DylinkError`, never panic,
+// and a parsed section must read back the same once written.
+fuzz_target!(|data: &[u8]| {
+    if let Ok(dylink) = Dylink::check_dylink(data) {
+        assert!(dylink.needed_dynlibs.len() <= data.len());
+        let written = dylink.write_dylink(data).expect("a parsed section can be written");
+        assert_eq!(Dylink::check_dylink(&written).ok(), Some(dylink));
+    }
+});
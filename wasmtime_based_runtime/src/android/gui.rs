@@ -1,20 +1,24 @@
-use std::{cell::RefCell, collections::HashMap};
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    rc::{Rc, Weak},
+};
 
 use wasmtime::*;
 
 use crate::android::java;
 use crate::android::java::{CLASS, ENV};
-use crate::global::{access_immutable_memory};
+use crate::global::{access_immutable_memory, Runtime};
+use crate::host::upgrade;
 
 thread_local! {
     pub static BUTTON_PRESSES: RefCell<HashMap<i32, u32>> = RefCell::new(HashMap::new());
     pub static ON_TICK_HANDLERS: RefCell<Vec<u32>> = RefCell::new(Vec::new());
 }
 
-fn get_string_from_wasm_memory(str_ptr: i32) -> String {
-    use crate::global::wasm_memory;
+fn get_string_from_wasm_memory(runtime: &Weak<Runtime>, str_ptr: i32) -> String {
     let ret = || -> Result<String, Trap> {
-        let memory = wasm_memory()?;
+        let memory = upgrade(runtime)?.wasm_memory()?;
         unsafe { access_immutable_memory(&memory, str_ptr) }
     }();
     match ret {
@@ -39,10 +43,11 @@ fn get_string_from_wasm_memory(str_ptr: i32) -> String {
 //     java::create_text_view(&env, &class, text)
 // }
 
-pub fn create_text_view(store: &Store) -> Func {
-    Func::wrap(&store, |_: Caller<'_>, text: i32| -> i32 {
+pub fn create_text_view(store: &Store, runtime: &Rc<Runtime>) -> Func {
+    let runtime = Rc::downgrade(runtime);
+    Func::wrap(&store, move |_: Caller<'_>, text: i32| -> i32 {
         //Get the label text from memory
-        let text = get_string_from_wasm_memory(text);
+        let text = get_string_from_wasm_memory(&runtime, text);
 
         //Get the java environment
         ENV.with(|vm_ref| {
@@ -74,10 +79,11 @@ pub fn create_text_view(store: &Store) -> Func {
 //     java::modify_text_view(&env, &class, id, text)
 // }
 
-pub fn modify_text_view(store: &Store) -> Func {
-    Func::wrap(&store, |_: Caller<'_>, id, text: i32| {
+pub fn modify_text_view(store: &Store, runtime: &Rc<Runtime>) -> Func {
+    let runtime = Rc::downgrade(runtime);
+    Func::wrap(&store, move |_: Caller<'_>, id, text: i32| {
         //Get the label text from memory
-        let text = get_string_from_wasm_memory(text);
+        let text = get_string_from_wasm_memory(&runtime, text);
 
         //Get the java environment
         ENV.with(|vm_ref| {
@@ -138,10 +144,11 @@ pub fn remove_text_view(store: &Store) -> Func {
 //     java::create_button(&env, &class, label)
 // }
 
-pub fn create_button(store: &Store) -> Func {
-    Func::wrap(&store, |_: Caller<'_>, label: i32| -> i32 {
+pub fn create_button(store: &Store, runtime: &Rc<Runtime>) -> Func {
+    let runtime = Rc::downgrade(runtime);
+    Func::wrap(&store, move |_: Caller<'_>, label: i32| -> i32 {
         //Get the label text from memory
-        let label = get_string_from_wasm_memory(label);
+        let label = get_string_from_wasm_memory(&runtime, label);
 
         //Get the java environment
         ENV.with(|vm_ref| {
@@ -461,21 +468,25 @@ pub fn canvas_delete_bitmap(store: &Store) -> Func {
 //     java::create_text(&env, &class, text, color, text_size)
 // }
 
-pub fn create_text(store: &Store) -> Func {
-    Func::wrap(&store, |_: Caller<'_>, text, color, text_size| -> i32 {
-        let text = get_string_from_wasm_memory(text);
-        //Get the java environment
-        ENV.with(|vm_ref| {
-            let e_brw = vm_ref.borrow();
-            let env = e_brw.as_ref().unwrap().get_env().unwrap();
-            //Get the class.
-            CLASS.with(|class_ref| {
-                let c_brw = class_ref.borrow();
-                let class = c_brw.as_ref().unwrap().as_obj();
-                java::create_text(&env, &class, &text, color, text_size)
+pub fn create_text(store: &Store, runtime: &Rc<Runtime>) -> Func {
+    let runtime = Rc::downgrade(runtime);
+    Func::wrap(
+        &store,
+        move |_: Caller<'_>, text, color, text_size| -> i32 {
+            let text = get_string_from_wasm_memory(&runtime, text);
+            //Get the java environment
+            ENV.with(|vm_ref| {
+                let e_brw = vm_ref.borrow();
+                let env = e_brw.as_ref().unwrap().get_env().unwrap();
+                //Get the class.
+                CLASS.with(|class_ref| {
+                    let c_brw = class_ref.borrow();
+                    let class = c_brw.as_ref().unwrap().as_obj();
+                    java::create_text(&env, &class, &text, color, text_size)
+                })
             })
-        })
-    })
+        },
+    )
 }
 
 // pub fn set_text(ctx: &mut Ctx, text_id: i32, text: WasmPtr<u8, Array>) {
@@ -493,9 +504,10 @@ pub fn create_text(store: &Store) -> Func {
 //     java::set_text(&env, &class, text_id, text);
 // }
 
-pub fn set_text(store: &Store) -> Func {
-    Func::wrap(&store, |_: Caller<'_>, text_id, text| {
-        let text = get_string_from_wasm_memory(text);
+pub fn set_text(store: &Store, runtime: &Rc<Runtime>) -> Func {
+    let runtime = Rc::downgrade(runtime);
+    Func::wrap(&store, move |_: Caller<'_>, text_id, text| {
+        let text = get_string_from_wasm_memory(&runtime, text);
         //Get the java environment
         ENV.with(|vm_ref| {
             let e_brw = vm_ref.borrow();
@@ -1,10 +1,14 @@
 use manager::Manager;
 
+use std::cell::RefCell;
 use std::collections::HashMap;
-use std::{cell::RefCell};
-use std::{sync::Mutex};
+use std::rc::Rc;
+use std::time::Duration;
 
-use crate::leb128::Dylink;
+use crate::limits::ResourceLimits;
+use crate::symbols::SymbolTable;
+use crate::watchdog::Watchdog;
+use dylink::Dylink;
 use wasmtime::*;
 
 pub struct ErrorInformation {
@@ -20,120 +24,193 @@ pub struct InstanceInfo {
     pub dylink: Option<Dylink>,
     pub memory_base: i32,
     pub table_base: Option<u32>,
+    /// Outstanding `dlopen` calls and modules linked against this one. Once it drops
+    /// to zero the module is unloaded, and its entry stays behind so handles remain stable.
+    pub references: u32,
+    /// Start of the `wasm_malloc`'d region holding the module's data.
+    pub allocation: Option<u32>,
+    /// Handles of the modules linked for this one, released when it is unloaded.
+    pub needed: Vec<u32>,
+    /// Table slots `function_slot` filled with functions of this module.
+    pub function_slots: Vec<u32>,
+    /// Opened with `RTLD_GLOBAL`, so its exports resolve the imports of any module.
+    pub global: bool,
+    /// `GOT.func` entries of a module opened with `RTLD_LAZY` that no module provided yet.
+    pub unbound: Vec<(String, Global)>,
 }
 
-thread_local!(
-    pub static MAIN: RefCell<Option<Func>> = RefCell::new(None);
-    pub static MANAGER: RefCell<Option<Manager>> = RefCell::new(None);
-
-    pub static INSTANCES: Mutex<Vec<InstanceInfo>> = Mutex::new(Vec::new());
-    pub static LINKER: Mutex<Option<Linker>> = Mutex::new(None);
-    pub static DLERROR: RefCell<ErrorInformation> = RefCell::new(ErrorInformation {
-        size: 256,
-        position: 0,
-        occurred: false,
-        memory: None,
-    });
-
-    pub static GOT_FUNC: RefCell<HashMap<String, u32>> = RefCell::new(HashMap::new());
-    pub static GOT_MEM: RefCell<HashMap<String, i32>> = RefCell::new(HashMap::new());
-
-    pub static MEMORY: RefCell<Option<Memory>> = RefCell::new(None);
-    pub static TABLE: RefCell<Option<Table>> = RefCell::new(None);
-    pub static MALLOC: RefCell<Option<Func>> = RefCell::new(None);
-);
-
-pub fn reset_globals() {
-    MAIN.with(|rc| {
-        let mut refmut = rc.borrow_mut();
-        *refmut = None;
-    });
-    MANAGER.with(|rc| {
-        let mut refmut = rc.borrow_mut();
-        *refmut = None;
-    });
-    INSTANCES.with(|m_vec| {
-        let mut vec = (*m_vec).lock().unwrap();
-        *vec = Vec::new();
-    });
-    LINKER.with(|m_ol| {
-        let mut ol = (*m_ol).lock().unwrap();
-        *ol = None;
-    });
-    DLERROR.with(|rc| {
-        let mut refmut = rc.borrow_mut();
-        *refmut = ErrorInformation {
-            size: 256,
-            position: 0,
-            occurred: false,
-            memory: None,
-        };
-    });
-    GOT_FUNC.with(|rc| {
-        let mut refmut = rc.borrow_mut();
-        *refmut = HashMap::new();
-    });
-    GOT_MEM.with(|rc| {
-        let mut refmut = rc.borrow_mut();
-        *refmut = HashMap::new();
-    });
-    MEMORY.with(|rc| {
-        let mut refmut = rc.borrow_mut();
-        *refmut = None;
-    });
-    TABLE.with(|rc| {
-        let mut refmut = rc.borrow_mut();
-        *refmut = None;
-    });
-    MALLOC.with(|rc| {
-        let mut refmut = rc.borrow_mut();
-        *refmut = None;
-    });
+impl InstanceInfo {
+    pub fn is_open(&self) -> bool {
+        self.references > 0
+    }
+}
+
+/// The state of one application: its main module, the side modules linked to it and the
+/// bookkeeping of the dynamic linker. Each `Runtime` has a `Store` of its own, so one
+/// process can run several applications side by side.
+pub struct Runtime {
+    pub main: RefCell<Option<Func>>,
+    pub manager: RefCell<Option<Manager>>,
+
+    pub instances: RefCell<Vec<InstanceInfo>>,
+    pub linker: RefCell<Option<Linker>>,
+    pub dlerror: RefCell<ErrorInformation>,
+
+    // Table slot of each function handed out through `GOT.func` or `dlsym`, by the index in
+    // `instances` of the module exporting it, `None` for host functions, and its name.
+    pub got_func: RefCell<HashMap<(Option<usize>, String), u32>>,
+
+    pub memory: RefCell<Option<Memory>>,
+    pub table: RefCell<Option<Table>>,
+    pub malloc: RefCell<Option<Func>>,
+    pub free: RefCell<Option<Func>>,
+    // Table slots of unloaded modules, reused by `insert_function`.
+    pub free_table_slots: RefCell<Vec<u32>>,
+    // NUL terminated copies of module and symbol names in the guest's memory, by name.
+    pub guest_strings: RefCell<HashMap<String, u32>>,
+    // Which module's export an `env` import or GOT entry resolves to.
+    pub symbols: RefCell<SymbolTable>,
+    pub limits: RefCell<ResourceLimits>,
+
+    // How long a call into the guest may run before it is interrupted.
+    pub budget: RefCell<Option<Duration>>,
+    // Handed to the watchdog when the first call with a budget starts it.
+    pub interrupt: RefCell<Option<InterruptHandle>>,
+    pub watchdog: RefCell<Option<Watchdog>>,
+    // Runs when the watchdog fired just after a call returned, to take the interrupt it left.
+    pub interrupt_sink: RefCell<Option<Func>>,
 }
 
-pub fn wasm_malloc(size: u32) -> Result<u32, Trap> {
-    MALLOC.with(|v| -> Result<u32, Trap> {
-        let func = v.borrow();
-        let malloc = func.clone().ok_or(Trap::new("MALLOC is None"))?;
+impl Runtime {
+    pub fn new() -> Rc<Self> {
+        Rc::new(Runtime {
+            main: RefCell::new(None),
+            manager: RefCell::new(None),
+            instances: RefCell::new(Vec::new()),
+            linker: RefCell::new(None),
+            dlerror: RefCell::new(ErrorInformation {
+                size: 256,
+                position: 0,
+                occurred: false,
+                memory: None,
+            }),
+            got_func: RefCell::new(HashMap::new()),
+            memory: RefCell::new(None),
+            table: RefCell::new(None),
+            malloc: RefCell::new(None),
+            free: RefCell::new(None),
+            free_table_slots: RefCell::new(Vec::new()),
+            guest_strings: RefCell::new(HashMap::new()),
+            symbols: RefCell::new(SymbolTable::new()),
+            limits: RefCell::new(ResourceLimits::default()),
+            budget: RefCell::new(None),
+            interrupt: RefCell::new(None),
+            watchdog: RefCell::new(None),
+            interrupt_sink: RefCell::new(None),
+        })
+    }
+
+    pub fn wasm_malloc(&self, size: u32) -> Result<u32, Trap> {
+        let malloc = self
+            .malloc
+            .borrow()
+            .clone()
+            .ok_or(Trap::new("MALLOC is None"))?;
         let malloc = malloc
             .get1::<u32, u32>()
             .or(Err(Trap::new("MALLOC signature doesnt match u32(u32)")))?;
         let result = malloc(size);
         Ok(result?)
-    })
-}
+    }
+
+    pub fn wasm_free(&self, pointer: u32) -> Result<(), Trap> {
+        let free = self
+            .free
+            .borrow()
+            .clone()
+            .ok_or(Trap::new("FREE is None"))?;
+        let free = free
+            .get1::<u32, ()>()
+            .or(Err(Trap::new("FREE signature doesnt match void(u32)")))?;
+        Ok(free(pointer)?)
+    }
 
-pub fn wasm_table() -> Result<Table, Trap> {
-    TABLE.with(|v| -> Result<Table, Trap> {
-        let table = v.borrow().clone().ok_or(Trap::new("TABLE is None"))?;
+    pub fn wasm_table(&self) -> Result<Table, Trap> {
+        let table = self
+            .table
+            .borrow()
+            .clone()
+            .ok_or(Trap::new("TABLE is None"))?;
         Ok(table)
-    })
-}
+    }
+
+    /// Grows `table` by `delta` slots, unless that exceeds the table size limit, and
+    /// returns its previous size.
+    pub fn grow_table(&self, table: &Table, delta: u32, init: Val) -> Result<u32, Trap> {
+        self.limits
+            .borrow()
+            .check_table(table.size(), delta)
+            .map_err(|e| Trap::new(e.to_string()))?;
+        Ok(table.grow(delta, init)?)
+    }
 
-pub fn wasm_memory() -> Result<Memory, Trap> {
-    MEMORY.with(|v| -> Result<Memory, Trap> {
-        let memory = v.borrow().clone().ok_or(Trap::new("MEMORY is None"))?;
+    pub fn wasm_memory(&self) -> Result<Memory, Trap> {
+        let memory = self
+            .memory
+            .borrow()
+            .clone()
+            .ok_or(Trap::new("MEMORY is None"))?;
         Ok(memory)
-    })
-}
+    }
 
-pub unsafe fn access_immutable_memory(memory: &Memory, name: i32) -> Result<String, Trap> {
-    let data: &[u8] = &memory.data_unchecked()[name as usize..];
-    for (index, byte) in (&data).iter().enumerate() {
-        if byte.to_owned() as char == '\0' {
-            match std::str::from_utf8(&data[..index]) {
-                Ok(s) => return Ok(s.to_string()),
-                Err(e) => return Err(Trap::new(e.to_string())),
+    /// Address of a NUL terminated copy of `string` in the guest's memory. Copies are
+    /// made once and never freed, so guests may keep the pointers.
+    pub fn guest_string(&self, string: &str) -> Result<u32, Trap> {
+        if let Some(pointer) = self.guest_strings.borrow().get(string).cloned() {
+            return Ok(pointer);
+        }
+
+        let memory = self.wasm_memory()?;
+        let pointer = self.wasm_malloc(string.len() as u32 + 1)?;
+        let start = pointer as usize;
+        unsafe {
+            let data = memory.data_unchecked_mut();
+            if start + string.len() >= data.len() {
+                return Err(Trap::new("malloc returned memory outside of the memory"));
             }
+            data[start..start + string.len()].copy_from_slice(string.as_bytes());
+            data[start + string.len()] = b'\0';
         }
+        self.guest_strings
+            .borrow_mut()
+            .insert(string.to_string(), pointer);
+        Ok(pointer)
     }
 
-    Err(Trap::new("Data has no '\0' byte"))
-}
+    /// Stores `values` as consecutive little endian u32s at `pointer`, the layout of a
+    /// struct of 32-bit fields in the guest's memory.
+    pub fn write_u32s(&self, pointer: i32, values: &[u32]) -> Result<(), Trap> {
+        if pointer <= 0 {
+            return Err(Trap::new(format!("Invalid pointer {:#x}", pointer)));
+        }
+        let memory = self.wasm_memory()?;
+        let start = pointer as usize;
+        let end = start + 4 * values.len();
+        unsafe {
+            let data = memory.data_unchecked_mut();
+            if end > data.len() {
+                return Err(Trap::new(format!("Invalid pointer {:#x}", pointer)));
+            }
+            for (index, value) in values.iter().enumerate() {
+                data[start + 4 * index..start + 4 * index + 4]
+                    .copy_from_slice(&value.to_le_bytes());
+            }
+        }
+        Ok(())
+    }
 
-pub fn write_error(error_message: &str) {
-    DLERROR.with(|error_ref| {
-        let mut error = error_ref.borrow_mut();
+    pub fn write_error(&self, error_message: &str) {
+        let mut error = self.dlerror.borrow_mut();
         assert!(error.size >= 1);
         error.occurred = true;
         let memory = error.memory.clone().unwrap();
@@ -143,5 +220,19 @@ pub fn write_error(error_message: &str) {
                 .copy_from_slice(error_message[..error_len].as_bytes());
             memory.data_unchecked_mut()[error.position + error_len] = b'\0';
         }
-    })
+    }
+}
+
+pub unsafe fn access_immutable_memory(memory: &Memory, name: i32) -> Result<String, Trap> {
+    let data: &[u8] = &memory.data_unchecked()[name as usize..];
+    for (index, byte) in (&data).iter().enumerate() {
+        if byte.to_owned() as char == '\0' {
+            match std::str::from_utf8(&data[..index]) {
+                Ok(s) => return Ok(s.to_string()),
+                Err(e) => return Err(Trap::new(e.to_string())),
+            }
+        }
+    }
+
+    Err(Trap::new("Data has no '\0' byte"))
 }
@@ -3,18 +3,29 @@ use wasmtime_wasi::{Wasi, WasiCtx};
 
 use manager::Manager;
 
+use std::cell::RefCell;
 use std::collections::HashMap;
+use std::fmt;
+use std::rc::{Rc, Weak};
+use std::time::Duration;
 
 use wasmtime::*;
 
-use crate::global::{
-    access_immutable_memory, wasm_malloc, wasm_memory, wasm_table, write_error, InstanceInfo,
-    DLERROR, GOT_FUNC, GOT_MEM, INSTANCES, LINKER, MAIN, MALLOC, MANAGER, MEMORY, TABLE,
-};
-use crate::leb128::Dylink;
+use crate::global::{access_immutable_memory, InstanceInfo, Runtime};
+use crate::limits::{LimitError, ResourceLimits};
+use crate::symbols::MAIN_PRIORITY;
+use crate::watchdog::Watchdog;
+use dylink::Dylink;
 
 pub type OurResult<T> = Result<T, Box<dyn std::error::Error>>;
 
+// Flags of `dlopen_with_flags`, with the values of emscripten's <dlfcn.h>.
+pub const RTLD_LAZY: i32 = 0x1;
+pub const RTLD_NOW: i32 = 0x2;
+pub const RTLD_NOLOAD: i32 = 0x4;
+pub const RTLD_GLOBAL: i32 = 0x100;
+pub const RTLD_LOCAL: i32 = 0;
+
 #[macro_export(local_inner_macros)]
 macro_rules! info {
     ($($arg:tt)+) => (
@@ -25,6 +36,16 @@ macro_rules! info {
     )
 }
 
+#[macro_export(local_inner_macros)]
+macro_rules! warn {
+    ($($arg:tt)+) => (
+        #[cfg(feature = "use_log")]
+        log::warn!($($arg)+);
+        #[cfg(feature = "use_println")]
+        std::println!($($arg)+);
+    )
+}
+
 #[macro_export(local_inner_macros)]
 macro_rules! error {
     ($($arg:tt)+) => (
@@ -35,57 +56,429 @@ macro_rules! error {
     )
 }
 
-fn insert_function(
-    table: Table,
-    func: Func,
-    function_name: String,
-    got: &mut HashMap<String, u32>,
-) -> Result<u32, Trap> {
+/// The runtime of a host function. Host functions only hold on to it weakly, as it
+/// owns the store that holds them.
+pub fn upgrade(runtime: &Weak<Runtime>) -> Result<Rc<Runtime>, Trap> {
+    runtime
+        .upgrade()
+        .ok_or_else(|| Trap::new("The runtime of this host function was dropped"))
+}
+
+fn insert_function(runtime: &Runtime, table: Table, func: Func) -> Result<u32, Trap> {
     if table.size() == 0 {
         // prevent return of 0 index
-        table.grow(1, Val::FuncRef(None))?;
+        runtime.grow_table(&table, 1, Val::FuncRef(None))?;
     }
-    table.grow(1, func.into())?;
-    got.insert(function_name, table.size() - 1);
-    Ok(table.size() - 1)
+    let free_slot = runtime.free_table_slots.borrow_mut().pop();
+    let index = match free_slot {
+        Some(index) => {
+            table.set(index, func.into())?;
+            index
+        }
+        None => {
+            runtime.grow_table(&table, 1, func.into())?;
+            table.size() - 1
+        }
+    };
+    Ok(index)
 }
 
-pub fn dlerror(store: &Store) -> Func {
-    Func::wrap(&store, || -> u32 {
-        DLERROR.with(|error_ref| {
-            let mut error = error_ref.borrow_mut();
-            if !error.occurred {
-                0
-            } else {
-                error.occurred = false;
-                error.position as u32
-            }
-        })
+/// The table slot of the function `name` exported by the module at `owner` in
+/// `Runtime::instances`, or defined by the host if `owner` is `None`. The slot is
+/// taken on first use and belongs to the owner, which frees it when it is unloaded.
+fn function_slot(
+    runtime: &Runtime,
+    table: &Table,
+    owner: Option<usize>,
+    name: &str,
+    func: Func,
+) -> Result<u32, Trap> {
+    let key = (owner, name.to_string());
+    if let Some(slot) = runtime.got_func.borrow().get(&key) {
+        return Ok(*slot);
+    }
+
+    let slot = insert_function(runtime, table.clone(), func)?;
+    runtime.got_func.borrow_mut().insert(key, slot);
+    if let Some(owner) = owner {
+        runtime.instances.borrow_mut()[owner]
+            .function_slots
+            .push(slot);
+    }
+    Ok(slot)
+}
+
+pub fn dlerror(store: &Store, runtime: &Rc<Runtime>) -> Func {
+    let runtime = Rc::downgrade(runtime);
+    Func::wrap(&store, move || -> Result<u32, Trap> {
+        let runtime = upgrade(&runtime)?;
+        let mut error = runtime.dlerror.borrow_mut();
+        if !error.occurred {
+            Ok(0)
+        } else {
+            error.occurred = false;
+            Ok(error.position as u32)
+        }
     })
 }
 
+/// What the exported data symbol `name` of a side module is relative to: its
+/// `__tls_base` for thread local symbols and its `__memory_base` otherwise.
+fn symbol_base(
+    instance: &Instance,
+    dylink: Option<&Dylink>,
+    memory_base: i32,
+    name: &str,
+) -> Result<i32, Trap> {
+    match dylink {
+        Some(dylink) if dylink.is_tls_export(name) => instance
+            .get_global("__tls_base")
+            .and_then(|tls_base| tls_base.get().i32())
+            .ok_or_else(|| {
+                Trap::new(format!(
+                    "{} is thread local, but its module exports no i32 __tls_base",
+                    name
+                ))
+            }),
+        _ => Ok(memory_base),
+    }
+}
+
+/// Absolute address of the data symbol `name`, which `instance` of `module` exports
+/// as an i32 global holding the symbol's offset. `None` if there is no such global.
+fn data_address(
+    instance: &Instance,
+    dylink: Option<&Dylink>,
+    memory_base: i32,
+    name: &str,
+    module: &str,
+) -> Result<Option<i32>, Trap> {
+    let offset_global = match instance.get_global(name) {
+        Some(offset_global) => offset_global,
+        None => return Ok(None),
+    };
+    let offset = offset_global
+        .get()
+        .i32()
+        .ok_or_else(|| Trap::new(format!("global {} in {} is not i32", name, module)))?;
+    Ok(Some(
+        symbol_base(instance, dylink, memory_base, name)? + offset,
+    ))
+}
+
+fn instance_data_address(instance_info: &InstanceInfo, name: &str) -> Result<Option<i32>, Trap> {
+    data_address(
+        &instance_info.instance,
+        instance_info.dylink.as_ref(),
+        instance_info.memory_base,
+        name,
+        &instance_info.name,
+    )
+}
+
+/// Handle of the already linked module `id`, as returned by `dlopen`.
+fn instance_handle(runtime: &Runtime, id: &str) -> Option<u32> {
+    runtime
+        .instances
+        .borrow()
+        .iter()
+        .position(|instance_info| instance_info.is_open() && instance_info.name == id)
+        .map(|index| (index + 1) as u32)
+}
+
+/// Adds a reference to the open module `handle`. Opening it with `RTLD_GLOBAL`
+/// makes its exports visible to all modules linked from now on.
+fn acquire(runtime: &Runtime, handle: u32, flags: i32) {
+    let mut instances = runtime.instances.borrow_mut();
+    let instance_info = &mut instances[(handle - 1) as usize];
+    instance_info.references += 1;
+    instance_info.global |= flags & RTLD_GLOBAL != 0;
+}
+
+/// Whether the exports of the module at `index` of `Runtime::instances` may resolve the imports
+/// of a module that needs the modules `needed`. Only modules opened with `RTLD_GLOBAL`,
+/// such as the main module, and a module's own needed libraries qualify.
+fn is_visible(index: usize, instance_info: &InstanceInfo, needed: &[u32]) -> bool {
+    instance_info.is_open() && (instance_info.global || needed.contains(&((index + 1) as u32)))
+}
+
+/// Records that the module at `index` of `Runtime::instances` uses an export of the one at
+/// `provider`, which then stays loaded until `index` is unloaded. The main module is never
+/// unloaded, and modules `index` already needs hold a reference already.
+fn depend_on(runtime: &Runtime, index: usize, provider: usize) {
+    let handle = (provider + 1) as u32;
+    {
+        let mut instances = runtime.instances.borrow_mut();
+        if index == provider
+            || instances[provider].dylink.is_none()
+            || instances[index].needed.contains(&handle)
+        {
+            return;
+        }
+        instances[index].needed.push(handle);
+    }
+    acquire(runtime, handle, 0);
+}
+
+/// Drops a reference to the module `handle` and unloads it once none are left.
+fn release(runtime: &Runtime, handle: u32) -> Result<(), Trap> {
+    let unload = {
+        let mut instances = runtime.instances.borrow_mut();
+        let instance_info = handle
+            .checked_sub(1)
+            .and_then(|index| instances.get_mut(index as usize))
+            .filter(|instance_info| instance_info.is_open())
+            .ok_or_else(|| Trap::new(format!("No open module with handle {}", handle)))?;
+        if instance_info.dylink.is_none() {
+            return Err(Trap::new(format!(
+                "The main module {} cannot be closed",
+                instance_info.name
+            )));
+        }
+        instance_info.references -= 1;
+        instance_info.references == 0
+    };
+
+    if unload {
+        unload_module(runtime, handle)?;
+    }
+    Ok(())
+}
+
+/// Runs the destructors of the module `handle`, frees its data and table slots,
+/// drops the `GOT.func` slots pointing into it and releases the modules it needed.
+/// The instance itself lives as long as the store.
+fn unload_module(runtime: &Runtime, handle: u32) -> Result<(), Trap> {
+    let index = (handle - 1) as usize;
+    let (name, instance) = {
+        let instances = runtime.instances.borrow();
+        (
+            instances[index].name.clone(),
+            instances[index].instance.clone(),
+        )
+    };
+    info!("Unloading {}", name);
+
+    // No borrow is held here, destructors may call dlclose themselves.
+    if let Some(call_dtors) = instance.get_func("__wasm_call_dtors") {
+        call_generated(runtime, &name, "__wasm_call_dtors", &call_dtors)?;
+    }
+
+    let resources = take_resources(&mut runtime.instances.borrow_mut()[index]);
+    free_resources(runtime, &name, resources)
+}
+
+/// The table slots, data allocation and needed modules of `instance_info`, which are
+/// left empty.
+fn take_resources(instance_info: &mut InstanceInfo) -> (Vec<u32>, Option<u32>, Vec<u32>) {
+    let mut slots = std::mem::take(&mut instance_info.function_slots);
+    if let (Some(table_base), Some(dylink)) = (instance_info.table_base, &instance_info.dylink) {
+        slots.extend(table_base..table_base + dylink.table_size);
+    }
+    (
+        slots,
+        instance_info.allocation.take(),
+        std::mem::take(&mut instance_info.needed),
+    )
+}
+
+/// Gives the table slots and data of the module `name` back, drops the `GOT.func` slots
+/// pointing into it and its symbols, and releases the modules it needed.
+fn free_resources(
+    runtime: &Runtime,
+    name: &str,
+    (slots, allocation, needed): (Vec<u32>, Option<u32>, Vec<u32>),
+) -> Result<(), Trap> {
+    let table = runtime.wasm_table()?;
+    for slot in &slots {
+        table.set(*slot, Val::FuncRef(None))?;
+    }
+    runtime
+        .got_func
+        .borrow_mut()
+        .retain(|_, &mut slot| !slots.contains(&slot));
+    runtime.free_table_slots.borrow_mut().extend(slots);
+
+    if let Some(allocation) = allocation {
+        if let Err(err) = runtime.wasm_free(allocation) {
+            error!("Leaking the data of {}: {}", name, err);
+        }
+    }
+
+    runtime.symbols.borrow_mut().remove(name);
+
+    for needed in needed {
+        release(runtime, needed)?;
+    }
+    Ok(())
+}
+
+/// Links the binaries returned by `Manager::load` and returns the handle of the
+/// requested module, which comes first. Libraries named in the `needed_dynlibs` of
+/// a binary are linked before it, and loaded through `manager` if necessary.
 fn link_binaries(
-    binaries: &Vec<(manager::Dependency, Vec<u8>)>,
+    runtime: &Rc<Runtime>,
+    manager: &mut Manager,
+    binaries: Vec<(manager::Dependency, Vec<u8>)>,
+    flags: i32,
     linker: &mut Linker,
 ) -> Result<u32, Trap> {
-    let mut loaded_module_ids = HashMap::new();
-    INSTANCES.with(|i| {
-        let instances = (*i).lock().unwrap();
-        for instance_info in instances.iter() {
-            loaded_module_ids.insert(instance_info.name.clone(), instance_info.instance.clone());
+    let requested = match binaries.first() {
+        Some((dependency, _)) => dependency.id.clone(),
+        None => return Err(Trap::new("No binaries to link")),
+    };
+    let order: Vec<String> = binaries.iter().map(|(d, _)| d.id.clone()).collect();
+    let mut pending: HashMap<String, (manager::Dependency, Vec<u8>)> = binaries
+        .into_iter()
+        .map(|(dependency, binary)| (dependency.id.clone(), (dependency, binary)))
+        .collect();
+
+    let mut handles = vec![];
+    for id in order {
+        if let Some((dependency, binary)) = pending.remove(&id) {
+            let linked = link_module(
+                runtime,
+                manager,
+                dependency,
+                binary,
+                flags,
+                &mut pending,
+                &mut vec![],
+                linker,
+            );
+            match linked {
+                Ok(handle) => handles.push(handle),
+                Err(err) => {
+                    // Closes the modules linked so far, which nothing holds on to yet.
+                    for handle in handles {
+                        if let Err(cleanup) = release(runtime, handle) {
+                            error!(
+                                "Cannot release {} after failing to link {}: {}",
+                                handle, id, cleanup
+                            );
+                        }
+                    }
+                    return Err(err);
+                }
+            }
         }
-    });
+    }
 
-    for (dependency, binary) in binaries {
-        if let Some(instance) = loaded_module_ids.get(&dependency.id) {
-            continue;
-        }
-        let info = Dylink::check_dylink(&binary)?;
+    // The requested module holds the references to its load-time dependencies.
+    let handle = instance_handle(runtime, &requested)
+        .ok_or_else(|| Trap::new(format!("{} was not linked", requested)))?;
+    let dependencies = handles.into_iter().filter(|h| *h != handle);
+    runtime.instances.borrow_mut()[(handle - 1) as usize]
+        .needed
+        .extend(dependencies);
+    Ok(handle)
+}
 
-        let table = wasm_table()?;
-        let table_base = table.grow(info.table_size, Val::FuncRef(None))?;
+/// Links the needed libraries of `binary` and then `binary` itself, and returns its handle
+/// with a reference taken for the caller. `flags` are those of the `dlopen` that caused
+/// the linking. `linking` holds the modules whose needed libraries are being linked, to
+/// detect cycles.
+#[allow(clippy::too_many_arguments)]
+fn link_module(
+    runtime: &Rc<Runtime>,
+    manager: &mut Manager,
+    dependency: manager::Dependency,
+    binary: Vec<u8>,
+    flags: i32,
+    pending: &mut HashMap<String, (manager::Dependency, Vec<u8>)>,
+    linking: &mut Vec<String>,
+    linker: &mut Linker,
+) -> Result<u32, Trap> {
+    if let Some(handle) = instance_handle(runtime, &dependency.id) {
+        acquire(runtime, handle, flags);
+        return Ok(handle);
+    }
+    if linking.contains(&dependency.id) {
+        return Err(Trap::new(format!(
+            "Cyclic needed_dynlibs: {} -> {}",
+            linking.join(" -> "),
+            dependency.id
+        )));
+    }
+    let info = Dylink::check_dylink(&binary)
+        .map_err(|e| Trap::new(format!("Cannot link {}: {}", dependency.id, e)))?;
 
-        let memory_base = wasm_malloc(info.mem_size)? as i32;
+    let over_limit = |e: LimitError| Trap::new(format!("Cannot link {}: {}", dependency.id, e));
+    {
+        let limits = runtime.limits.borrow();
+        // The main module is the first instance and does not count.
+        let open = runtime
+            .instances
+            .borrow()
+            .iter()
+            .skip(1)
+            .filter(|i| i.is_open())
+            .count();
+        limits.check_modules(open).map_err(over_limit)?;
+        limits
+            .check_module_data(info.mem_size)
+            .map_err(over_limit)?;
+    }
+
+    linking.push(dependency.id.clone());
+    let needed = link_needed_dynlibs(
+        runtime,
+        manager,
+        &dependency.id,
+        &info,
+        flags,
+        pending,
+        linking,
+        linker,
+    )?;
+    linking.pop();
+
+    // What linking took so far, given back by a single path if it fails: the table slots
+    // it placed the module at, its data, and its index in `Runtime::instances` once registered.
+    let mut placed = None;
+    let mut allocated = None;
+    let mut own = None;
+    let linked = || -> Result<usize, Trap> {
+        let table = runtime.wasm_table()?;
+        let (grow_by, table_base) = info
+            .table_placement(table.size())
+            .ok_or_else(|| Trap::new(format!("The table cannot hold {}", dependency.id)))?;
+        runtime
+            .grow_table(&table, grow_by, Val::FuncRef(None))
+            .map_err(|e| Trap::new(format!("Cannot link {}: {}", dependency.id, e)))?;
+        placed = Some(table_base);
+
+        // The allocator only guarantees its own alignment, so the data is placed at
+        // the first suitably aligned address of a larger allocation.
+        let too_large = || {
+            Trap::new(format!(
+                "The data of {} does not fit the memory",
+                dependency.id
+            ))
+        };
+        let allocation_size = info.memory_allocation_size().ok_or_else(too_large)?;
+        // Assumes malloc has to grow the memory by the whole allocation.
+        let pages =
+            runtime.wasm_memory()?.size() as u64 + (allocation_size as u64).div_ceil(0x10000);
+        runtime
+            .limits
+            .borrow()
+            .check_memory(pages)
+            .map_err(over_limit)?;
+        let allocation = runtime.wasm_malloc(allocation_size)?;
+        if allocation == 0 {
+            return Err(too_large());
+        }
+        allocated = Some(allocation);
+        // malloc may have grown the memory past the limit. It cannot shrink again, but the
+        // module is not loaded and the allocation is available to the next one.
+        let pages = runtime.wasm_memory()?.size() as u64;
+        runtime
+            .limits
+            .borrow()
+            .check_memory(pages)
+            .map_err(over_limit)?;
+        let memory_base = info.memory_base(allocation).ok_or_else(too_large)? as i32;
         let memory_base_global = Global::new(
             linker.store(),
             GlobalType::new(ValType::I32, Mutability::Const),
@@ -103,13 +496,29 @@ fn link_binaries(
         linker.define(
             "host",
             "dlopen",
-            dlopen(linker.store(), dependency.id.clone()),
+            dlopen(linker.store(), runtime, dependency.id.clone()),
+        )?;
+        linker.define(
+            "host",
+            "dlopen_with_flags",
+            dlopen_with_flags(linker.store(), runtime, dependency.id.clone()),
         )?;
 
         let module = Module::from_binary(linker.store().engine(), &binary)?;
 
+        let lazy = flags & RTLD_NOW == 0;
+        let weak_imports = manager.weak_imports(&dependency.id);
+        let is_weak = |module: &str, name: &str| {
+            info.is_weak_import(module, name) || weak_imports.iter().any(|weak| weak == name)
+        };
+
         let mut globals_func = HashMap::new();
         let mut globals_mem = HashMap::new();
+        // Imports from other modules. They are not defined in the linker, where later
+        // modules would see them regardless of the symbol table and `RTLD_LOCAL`.
+        let mut module_imports = HashMap::new();
+        // Modules the imports resolved to, as indices into `Runtime::instances`.
+        let mut providers = vec![];
 
         for import in module.imports() {
             if import.module() == "GOT.func" {
@@ -133,256 +542,877 @@ fn link_binaries(
             } else if import.module() == "env" {
                 let exter = linker.get_one_by_name(import.module(), import.name());
                 if exter.is_err() {
-                    let exter = INSTANCES.with(|instance_ref| {
-                        let instances = (*instance_ref).lock().unwrap();
-                        for instance_info in &*instances {
-                            let export = instance_info.instance.get_export(import.name());
-                            if export.is_some() {
-                                return export;
-                            }
-                        }
-                        None
+                    let exter = find_export(runtime, import.name(), &needed, |instance_info| {
+                        instance_info.instance.get_export(import.name())
                     });
 
-                    if let Some(item) = exter {
-                        linker.define("env", import.name(), item)?;
-                    } else {
-                        return Err(Trap::new(format!(
-                            "Couldn't find {}::{}",
-                            import.module(),
-                            import.name()
-                        )));
+                    match exter {
+                        Some((owner, item)) => {
+                            providers.push(owner);
+                            module_imports.insert(import.name().to_string(), item);
+                        }
+                        None => {
+                            let weak = is_weak(import.module(), import.name());
+                            let item: Extern = match import.ty() {
+                                ExternType::Func(ty) if lazy || weak => unresolved_function(
+                                    linker.store(),
+                                    runtime,
+                                    &dependency.id,
+                                    import.name(),
+                                    ty,
+                                    if lazy { Some(needed.clone()) } else { None },
+                                )
+                                .into(),
+                                ExternType::Global(ty) if weak => {
+                                    weak_global(linker.store(), ty)?.into()
+                                }
+                                _ => {
+                                    return Err(Trap::new(format!(
+                                        "Couldn't find {}::{}",
+                                        import.module(),
+                                        import.name()
+                                    )))
+                                }
+                            };
+                            module_imports.insert(import.name().to_string(), item);
+                        }
                     }
                 }
             }
         }
 
-        let instance = linker.instantiate(&module).or_else(|err| {
-            Err(Trap::new(format!(
+        let mut imports = vec![];
+        for import in module.imports() {
+            let item = match module_imports.get(import.name()) {
+                Some(item) if import.module() == "env" => item.clone(),
+                _ => linker
+                    .get_one_by_name(import.module(), import.name())
+                    .map_err(|err| {
+                        Trap::new(format!(
+                            "Unable to link `{}` module: {}",
+                            dependency.id, err
+                        ))
+                    })?,
+            };
+            imports.push(item);
+        }
+
+        // Recorded before instantiating, so strict mode rejects the module before any of its code runs.
+        let exports = module
+            .exports()
+            .map(|export| {
+                (
+                    export.name().to_string(),
+                    info.is_weak_export(export.name()),
+                )
+            })
+            .collect();
+        define_symbols(runtime, &dependency.id, exports)?;
+        let instance = Instance::new(linker.store(), &module, &imports).map_err(|err| {
+            Trap::new(format!(
                 "Unable to instantiate `{}` module: {}",
                 dependency.id, err
-            )))
+            ))
         })?;
         linker.instance(&dependency.id, &instance)?;
 
-        GOT_MEM.with(|got_ref| -> Result<(), Trap> {
-            let mut got = got_ref.borrow_mut();
-            for (name, global) in globals_mem {
-                let memory_index: Result<i32, Trap> = match got.get(name) {
-                    Some(index) => Ok(*index),
-                    None => {
-                        // didnt find from GOT
-                        let index = {
-                            // find from the currently instantiated instance
-                            if let Some(offset_global) = instance.get_global(name) {
-                                let index = memory_base + offset_global.get().i32()
-                                    .ok_or_else(|| Trap::new(format!(
-                                        "global {} in {} is not i32",
-                                        name, dependency.id
-                                    )))?;
-                                index
-                            } else {
-                                // find from any existing instance
-                                let index_option: Option<i32> = INSTANCES.with(|instance_ref| {
-                                    let instances = (*instance_ref).lock().unwrap();
-                                    for instance_info in &*instances {
-                                        if let Some(offset_global) = instance_info.instance.get_global(name)
-                                        {
-                                            if let Some(offset) = offset_global.get().i32() {
-                                                return Ok(Some(instance_info.memory_base + offset));
-                                            } else {
-                                                return Err(Trap::new(format!(
-                                                    "global {} in {} is not i32",
-                                                    name, dependency.id
-                                                )));
-                                            }
-                                        }
-                                    }
-                                    Ok(None)
-                                })?;
-
-                                if let Some(index) = index_option {
-                                    index
-                                } else {
-                                    return Err(Trap::new(format!(
-                                        "Couldn't find GOT.mem.{}",
-                                        name
-                                    )));
-                                }
-                            }
-                        };
-                        got.insert(String::from(name), index);
-                        Ok(index)
-                    }
+        // Registered before its GOT is filled, so that its own exports resolve through
+        // the symbol table like those of the modules it needs.
+        let index = {
+            let mut instances = runtime.instances.borrow_mut();
+            instances.push(InstanceInfo {
+                name: dependency.id.clone(),
+                instance: instance.clone(),
+                dylink: Some(info.clone()),
+                memory_base,
+                table_base: Some(table_base),
+                references: 1,
+                allocation: Some(allocation),
+                needed: needed.clone(),
+                function_slots: vec![],
+                global: flags & RTLD_GLOBAL != 0,
+                unbound: vec![],
+            });
+            instances.len() - 1
+        };
+        own = Some(index);
+        for provider in providers {
+            depend_on(runtime, index, provider);
+        }
+        let mut visible = needed.clone();
+        visible.push((index + 1) as u32);
+
+        // Sets up the thread local storage of the module and with it `__tls_base`,
+        // which the TLS exports are relative to.
+        if let Some(tls_init) = instance.get_func("_emscripten_tls_init") {
+            call_generated(runtime, &dependency.id, "_emscripten_tls_init", &tls_init)?;
+        }
+
+        // Resolved through the modules on every link, so an entry never points to a
+        // module that is not visible to this one or was unloaded since.
+        for (name, global) in globals_mem {
+            let provider = find_export(runtime, name, &visible, |instance_info| {
+                instance_info.instance.get_global(name)
+            });
+            let address = match provider {
+                Some((owner, _)) => {
+                    depend_on(runtime, index, owner);
+                    instance_data_address(&runtime.instances.borrow()[owner], name)?
+                }
+                None => None,
+            };
+            let address = match address {
+                Some(address) => address,
+                // Missing weak data is at address 0, which the entry already holds.
+                None if is_weak("GOT.mem", name) => continue,
+                None => return Err(Trap::new(format!("Couldn't find GOT.mem.{}", name))),
+            };
+
+            global.set(Val::I32(address))?;
+        }
+
+        let mut unbound = vec![];
+        for (name, global) in globals_func {
+            // The functions of the visible modules, this one included, come first and
+            // own their slot. Host functions come last.
+            let function_index =
+                if let Some((owner, func)) = visible_function(runtime, name, &visible) {
+                    depend_on(runtime, index, owner);
+                    function_slot(runtime, &table, Some(owner), name, func)?
+                } else if let Some(func) = host_function(runtime, linker, name) {
+                    function_slot(runtime, &table, None, name, func)?
+                } else if lazy {
+                    // Bound by `bind_unbound_functions` once a module provides it.
+                    unbound.push((name.to_string(), global));
+                    continue;
+                } else if is_weak("GOT.func", name) {
+                    // Stays a null function pointer, calls through it trap.
+                    continue;
+                } else {
+                    return Err(Trap::new(format!("Couldn't find GOT.func.{}", name)));
                 };
 
-                global.set(Val::I32(memory_index? as i32))?;
+            global.set(Val::I32(function_index as i32))?;
+        }
+        runtime.instances.borrow_mut()[index].unbound = unbound;
+
+        if let Some(post_instantiate) = instance.get_func("__post_instantiate") {
+            call_generated(
+                runtime,
+                &dependency.id,
+                "__post_instantiate",
+                &post_instantiate,
+            )?;
+        } else {
+            // Modules with a `dylink.0` section split `__post_instantiate` in two.
+            for name in &["__wasm_apply_data_relocs", "__wasm_call_ctors"] {
+                if let Some(func) = instance.get_func(name) {
+                    call_generated(runtime, &dependency.id, name, &func)?;
+                }
+            }
+        }
+
+        Ok(index)
+    }();
+    let index = match linked {
+        Ok(index) => index,
+        Err(err) => {
+            let resources = match own {
+                Some(index) => {
+                    let mut instances = runtime.instances.borrow_mut();
+                    let instance_info = &mut instances[index];
+                    // Closed, so nothing resolves to it anymore.
+                    instance_info.references = 0;
+                    instance_info.unbound.clear();
+                    take_resources(instance_info)
+                }
+                None => {
+                    let slots = placed.map_or(vec![], |table_base| {
+                        (table_base..table_base + info.table_size).collect()
+                    });
+                    (slots, allocated, needed)
+                }
+            };
+            if let Err(cleanup) = free_resources(runtime, &dependency.id, resources) {
+                error!(
+                    "Cannot clean up after failing to link {}: {}",
+                    dependency.id, cleanup
+                );
+            }
+            return Err(err);
+        }
+    };
+    bind_unbound_functions(runtime)?;
+
+    Ok((index + 1) as u32)
+}
+
+/// A function of type `ty` standing in for the import `name` of `module` that no module
+/// provides. With `needed`, the first call looks the function up among the modules visible
+/// by then and later calls go straight to it. Calls trap while it is still missing.
+fn unresolved_function(
+    store: &Store,
+    runtime: &Rc<Runtime>,
+    module: &str,
+    name: &str,
+    ty: FuncType,
+    needed: Option<Vec<u32>>,
+) -> Func {
+    let missing = format!("Called the unresolved function env::{} of {}", name, module);
+    let importer = module.to_string();
+    let name = name.to_string();
+    let runtime = Rc::downgrade(runtime);
+    let resolved: RefCell<Option<Func>> = RefCell::new(None);
+    Func::new(store, ty.clone(), move |_, params, results| {
+        let cached = resolved.borrow().clone();
+        let func = match (cached, &needed) {
+            (Some(func), _) => func,
+            (None, Some(needed)) => {
+                let runtime = upgrade(&runtime)?;
+                let (owner, func) = visible_function(&runtime, &name, needed)
+                    .ok_or_else(|| Trap::new(missing.clone()))?;
+                if func.ty() != ty {
+                    return Err(Trap::new(format!(
+                        "env::{} was resolved to a function of another type",
+                        name
+                    )));
+                }
+                if let Some(handle) = instance_handle(&runtime, &importer) {
+                    depend_on(&runtime, (handle - 1) as usize, owner);
+                }
+                *resolved.borrow_mut() = Some(func.clone());
+                func
+            }
+            (None, None) => return Err(Trap::new(missing.clone())),
+        };
+        let values = func.call(params)?;
+        results.clone_from_slice(&values);
+        Ok(())
+    })
+}
+
+/// A global of type `ty` holding 0, for a weak import that no module provides.
+fn weak_global(store: &Store, ty: GlobalType) -> Result<Global, Trap> {
+    let zero = match ty.content() {
+        ValType::I32 => Val::I32(0),
+        ValType::I64 => Val::I64(0),
+        ValType::F32 => Val::F32(0),
+        ValType::F64 => Val::F64(0),
+        content => {
+            return Err(Trap::new(format!(
+                "Weak globals of type {:?} are not supported",
+                content
+            )))
+        }
+    };
+    Ok(Global::new(store, ty, zero)?)
+}
+
+/// Records the exports of `module` in the symbol table and warns about the definitions
+/// that are shadowed from now on.
+fn define_symbols(
+    runtime: &Runtime,
+    module: &str,
+    exports: Vec<(String, bool)>,
+) -> Result<(), Trap> {
+    let shadowed = runtime
+        .symbols
+        .borrow_mut()
+        .define(module, exports)
+        .map_err(|err| Trap::new(format!("Cannot link {}: {}", module, err)))?;
+    for shadowed in shadowed {
+        warn!("{}", shadowed);
+    }
+    Ok(())
+}
+
+/// What `get` returns for the module that the symbol table prefers among those exporting
+/// `name` and visible to a module that needs `needed`, with the index of that module in
+/// `Runtime::instances`.
+fn find_export<T>(
+    runtime: &Runtime,
+    name: &str,
+    needed: &[u32],
+    get: impl Fn(&InstanceInfo) -> Option<T>,
+) -> Option<(usize, T)> {
+    let symbols = runtime.symbols.borrow();
+    let instances = runtime.instances.borrow();
+    let found = symbols.providers(name).find_map(|provider| {
+        let (index, instance_info) = instances
+            .iter()
+            .enumerate()
+            .find(|(_, instance_info)| instance_info.is_open() && instance_info.name == provider)?;
+        if !is_visible(index, instance_info, needed) {
+            return None;
+        }
+        get(instance_info).map(|item| (index, item))
+    });
+    found
+}
+
+/// The function `name` exported by a module visible to one that needs `needed`,
+/// with the index of that module in `Runtime::instances`.
+fn visible_function(runtime: &Runtime, name: &str, needed: &[u32]) -> Option<(usize, Func)> {
+    find_export(runtime, name, needed, |instance_info| {
+        instance_info.instance.get_func(name)
+    })
+}
+
+/// Rejects side modules that define symbols already defined by other modules.
+pub fn set_strict_symbols(runtime: &Runtime, strict: bool) {
+    runtime.symbols.borrow_mut().set_strict(strict);
+}
+
+/// Caps the memory, table and modules of the application. Limits apply to modules
+/// linked afterwards, so they are best set before `initialize`.
+pub fn set_limits(runtime: &Runtime, limits: ResourceLimits) {
+    runtime.limits.replace(limits);
+}
+
+/// Lets the exports of `module` win over those of modules with a lower priority,
+/// which is 0 unless set. Has to be called before `module` is loaded.
+pub fn set_symbol_priority(runtime: &Runtime, module: &str, priority: i32) {
+    runtime.symbols.borrow_mut().set_priority(module, priority);
+}
+
+/// The function `name` the host defined in `linker`. Modules are left out, as the
+/// linker also holds the exports of those opened with `RTLD_LOCAL`.
+fn host_function(runtime: &Runtime, linker: &Linker, name: &str) -> Option<Func> {
+    let modules: Vec<String> = runtime
+        .instances
+        .borrow()
+        .iter()
+        .map(|instance_info| instance_info.name.clone())
+        .collect();
+    linker
+        .iter()
+        .filter(|(module, item_name, _)| {
+            *item_name == name && !modules.iter().any(|m| m == *module)
+        })
+        .find_map(|(_, _, value)| value.into_func())
+}
+
+/// Binds the `GOT.func` entries that modules opened with `RTLD_LAZY` could not resolve
+/// when they were linked, as far as the modules linked since then provide them.
+fn bind_unbound_functions(runtime: &Runtime) -> Result<(), Trap> {
+    let waiting: Vec<(usize, Vec<u32>, Vec<(String, Global)>)> = runtime
+        .instances
+        .borrow_mut()
+        .iter_mut()
+        .enumerate()
+        .filter(|(_, instance_info)| instance_info.is_open() && !instance_info.unbound.is_empty())
+        .map(|(index, instance_info)| {
+            (
+                index,
+                instance_info.needed.clone(),
+                std::mem::take(&mut instance_info.unbound),
+            )
+        })
+        .collect();
+    if waiting.is_empty() {
+        return Ok(());
+    }
+
+    let table = runtime.wasm_table()?;
+    for (index, needed, entries) in waiting {
+        let mut still_unbound = vec![];
+        for (name, global) in entries {
+            let function_index = match visible_function(runtime, &name, &needed) {
+                Some((owner, func)) => {
+                    depend_on(runtime, index, owner);
+                    function_slot(runtime, &table, Some(owner), &name, func)?
+                }
+                None => {
+                    still_unbound.push((name, global));
+                    continue;
+                }
+            };
+            global.set(Val::I32(function_index as i32))?;
+        }
+
+        runtime.instances.borrow_mut()[index].unbound = still_unbound;
+    }
+    Ok(())
+}
+
+/// Links the libraries `info` says `module` needs, as if they were load-time dependencies,
+/// and returns their handles. Each one has to be a dependency in the metadata of `module`.
+#[allow(clippy::too_many_arguments)]
+fn link_needed_dynlibs(
+    runtime: &Rc<Runtime>,
+    manager: &mut Manager,
+    module: &str,
+    info: &Dylink,
+    flags: i32,
+    pending: &mut HashMap<String, (manager::Dependency, Vec<u8>)>,
+    linking: &mut Vec<String>,
+    linker: &mut Linker,
+) -> Result<Vec<u32>, Trap> {
+    let mut handles = vec![];
+    let linked = || -> Result<(), Trap> {
+        for library in &info.needed_dynlibs {
+            let needed = manager
+                .resolve_id(module, manager::library_id(library))
+                .map_err(|e| {
+                    Trap::new(format!(
+                        "{} needs {}, which is not a dependency in its metadata: {}",
+                        module, library, e
+                    ))
+                })?;
+            if let Some(handle) = instance_handle(runtime, &needed.id) {
+                acquire(runtime, handle, flags);
+                handles.push(handle);
+                continue;
+            }
+
+            let (needed, binary) = match pending.remove(&needed.id) {
+                Some(entry) => entry,
+                None => {
+                    let mut loaded = manager.load(needed)?.into_iter();
+                    let first = loaded.next().ok_or_else(|| {
+                        Trap::new(format!("Loading {} returned nothing", library))
+                    })?;
+                    for (dependency, binary) in loaded {
+                        pending
+                            .entry(dependency.id.clone())
+                            .or_insert((dependency, binary));
+                    }
+                    first
+                }
+            };
+            handles.push(link_module(
+                runtime, manager, needed, binary, flags, pending, linking, linker,
+            )?);
+        }
+        Ok(())
+    }();
+
+    // The libraries linked before the failure are not needed anymore.
+    if let Err(err) = linked {
+        for handle in handles {
+            if let Err(cleanup) = release(runtime, handle) {
+                error!(
+                    "Cannot release {} after failing to link {}: {}",
+                    handle, module, cleanup
+                );
             }
-            Ok(())
+        }
+        return Err(err);
+    }
+    Ok(handles)
+}
+
+/// Opens the module whose id `id_ptr` points to on behalf of `caller_module`, as
+/// described by the `RTLD_*` `flags`, and returns its handle or 0 after an error.
+fn open_module(runtime: &Rc<Runtime>, caller_module: &str, id_ptr: i32, flags: i32) -> u32 {
+    let ret = || -> Result<u32, Trap> {
+        let mut guard = runtime
+            .linker
+            .try_borrow_mut()
+            .map_err(|_| Trap::new("dlopen cannot be called while a module is being linked"))?;
+        let linker = guard.as_mut().ok_or_else(|| {
+            Trap::new("dlopen cannot be called before the runtime is initialized")
         })?;
 
-        GOT_FUNC.with(|got_ref| -> Result<(), Trap> {
-            let mut got = got_ref.borrow_mut();
-            for (name, global) in globals_func {
-                let function_index: Result<u32, Trap> = match got.get(name) {
-                    Some(index) => Ok(*index),
-                    None => {
-                        let func = || -> Result<Func, Trap> {
-                            let get_func = || {
-                                for (_, item_name, value) in linker.iter() {
-                                    if let Some(func) = value.into_func() {
-                                        if item_name == name {
-                                            return Some(func);
-                                        }
-                                    }
-                                }
-                                None
-                            };
+        if flags & (RTLD_LAZY | RTLD_NOW) == 0 {
+            return Err(Trap::new(format!(
+                "Invalid dlopen flags {:#x}: one of RTLD_LAZY and RTLD_NOW is required",
+                flags
+            )));
+        }
 
-                            if let Some(func) = get_func() {
-                                Ok(func)
-                            } else {
-                                return Err(Trap::new(format!("Couldn't find GOT.func.{}", name)));
-                            }
-                        }()?;
+        let memory = runtime.wasm_memory()?;
+        let id = unsafe { access_immutable_memory(&memory, id_ptr)? };
+        let mut manager = runtime.manager.borrow_mut();
+        let manager = manager.as_mut().ok_or_else(|| {
+            Trap::new("dlopen cannot be called before the runtime is initialized")
+        })?;
+        let dependency = manager.resolve_id(caller_module, &id)?;
 
-                        let index =
-                            insert_function(table.clone(), func, name.to_string(), &mut got)?;
+        if let Some(handle) = instance_handle(runtime, &dependency.id) {
+            acquire(runtime, handle, flags);
+            return Ok(handle);
+        }
+        // Only probing whether the module is loaded, which is not an error.
+        if flags & RTLD_NOLOAD != 0 {
+            return Ok(0);
+        }
+
+        let binaries = manager.load(dependency)?;
+        link_binaries(runtime, manager, binaries, flags, linker)
+    }();
+
+    if let Err(err) = ret {
+        error!("{}", &err.to_string());
+        runtime.write_error(&err.to_string());
+        return 0;
+    }
+
+    ret.unwrap()
+}
+
+/// `dlopen(id)`, which binds eagerly and makes the module's exports visible to all
+/// modules linked later, as before `dlopen_with_flags` existed.
+pub fn dlopen(store: &Store, runtime: &Rc<Runtime>, caller_module: String) -> Func {
+    let runtime = Rc::downgrade(runtime);
+    Func::wrap(
+        &store,
+        move |_: Caller<'_>, id_ptr: i32| -> Result<u32, Trap> {
+            Ok(open_module(
+                &upgrade(&runtime)?,
+                &caller_module,
+                id_ptr,
+                RTLD_NOW | RTLD_GLOBAL,
+            ))
+        },
+    )
+}
+
+/// `dlopen(id, flags)` with the `RTLD_*` flags of <dlfcn.h>. `RTLD_NOLOAD` returns the
+/// handle of an already open module and 0 otherwise, without loading anything.
+pub fn dlopen_with_flags(store: &Store, runtime: &Rc<Runtime>, caller_module: String) -> Func {
+    let runtime = Rc::downgrade(runtime);
+    Func::wrap(
+        &store,
+        move |_: Caller<'_>, id_ptr: i32, flags: i32| -> Result<u32, Trap> {
+            Ok(open_module(
+                &upgrade(&runtime)?,
+                &caller_module,
+                id_ptr,
+                flags,
+            ))
+        },
+    )
+}
 
-                        Ok(index)
+/// `dlsym(handle, name)` returns the table index of an exported function, which
+/// can be called through a function pointer, or the address of an exported data symbol.
+pub fn dlsym(store: &Store, runtime: &Rc<Runtime>) -> Func {
+    let runtime = Rc::downgrade(runtime);
+    Func::wrap(
+        &store,
+        move |_: Caller<'_>, handle: i32, function_name: i32| -> Result<u32, Trap> {
+            let runtime = upgrade(&runtime)?;
+            let ret = || -> Result<u32, Trap> {
+                let index = (handle as u32)
+                    .checked_sub(1)
+                    .map(|index| index as usize)
+                    .filter(|index| {
+                        runtime
+                            .instances
+                            .borrow()
+                            .get(*index)
+                            .is_some_and(InstanceInfo::is_open)
+                    })
+                    .ok_or_else(|| Trap::new(format!("No open module with handle {}", handle)))?;
+
+                let memory = runtime.wasm_memory()?;
+                let function_name = unsafe { access_immutable_memory(&memory, function_name)? };
+
+                let table = runtime.wasm_table()?;
+                let (side_instance, data) = {
+                    let instances = runtime.instances.borrow();
+                    let instance_info = &instances[index];
+                    (
+                        instance_info.instance.clone(),
+                        instance_data_address(instance_info, &function_name)?,
+                    )
+                };
+
+                // Exported data globals hold an offset, dlsym returns the absolute address.
+                let func = match side_instance.get_func(&function_name) {
+                    Some(func) => func,
+                    None => {
+                        return data.map(|address| address as u32).ok_or_else(|| {
+                            Trap::new(format!("{} was not found (dlsym)", function_name))
+                        })
                     }
                 };
 
-                global.set(Val::I32(function_index? as i32))?;
+                function_slot(&runtime, &table, Some(index), &function_name, func)
+            }();
+
+            if let Err(err) = ret {
+                error!("{}", &err.to_string());
+                runtime.write_error(&err.to_string());
+                return Ok(0);
             }
-            Ok(())
-        })?;
 
-        if let Some(post_instantiate) = instance.get_func("__post_instantiate") {
-            post_instantiate.call(&[])?;
+            ret
+        },
+    )
+}
+
+/// The open module whose data contains `address`, as an index into `Runtime::instances`. The main
+/// module owns the addresses below its `__heap_base` that no side module owns, if it
+/// exports one.
+fn data_owner(instances: &[InstanceInfo], address: i32) -> Option<usize> {
+    let side = instances.iter().position(|instance_info| {
+        let offset = address as i64 - instance_info.memory_base as i64;
+        match &instance_info.dylink {
+            Some(dylink) => {
+                instance_info.is_open() && offset >= 0 && offset < dylink.mem_size as i64
+            }
+            None => false,
         }
+    });
 
-        let len = INSTANCES.with(|i| {
-            let mut instances = (*i).lock().unwrap();
-            instances.push(InstanceInfo {
-                name: dependency.id.clone(),
-                instance: instance,
-                dylink: Some(info),
-                memory_base,
-                table_base: Some(table_base),
-            });
-            instances.len()
-        });
-    }
+    side.or_else(|| {
+        let main = instances
+            .iter()
+            .position(|instance_info| instance_info.dylink.is_none())?;
+        let heap_base = instances[main]
+            .instance
+            .get_global("__heap_base")?
+            .get()
+            .i32()?;
+        if address >= 0 && address < heap_base {
+            Some(main)
+        } else {
+            None
+        }
+    })
+}
 
-    // The module we load should be the last one we loaded in current implementation
-    let handle = INSTANCES.with(|i| {
-        let instances = (*i).lock().unwrap();
-        instances.len()
+/// The open module owning the table slot `index`: the side module whose functions were
+/// placed there, or whose function `function_slot` put there. Other slots belong to
+/// the main module's table.
+fn function_owner(instances: &[InstanceInfo], index: u32) -> Option<usize> {
+    let side = instances.iter().position(|instance_info| {
+        let placed = match (instance_info.table_base, &instance_info.dylink) {
+            (Some(table_base), Some(dylink)) => {
+                index >= table_base && index - table_base < dylink.table_size
+            }
+            _ => false,
+        };
+        instance_info.is_open() && (placed || instance_info.function_slots.contains(&index))
     });
 
-    Ok(handle as u32)
+    side.or_else(|| {
+        instances
+            .iter()
+            .position(|instance_info| instance_info.dylink.is_none())
+    })
 }
 
-pub fn dlopen(store: &Store, caller_module: String) -> Func {
-    Func::wrap(&store, move |_: Caller<'_>, id_ptr: i32| -> u32 {
-        let ret = LINKER.with(|m| -> Result<u32, Trap> {
-            let mut guard = m.lock().unwrap();
-            let a = guard.as_mut();
-            let linker = a.unwrap();
+/// The exported data symbol of `instance_info` closest to `address` from below, with its address.
+fn nearest_data_symbol(
+    instance_info: &InstanceInfo,
+    address: i32,
+) -> Result<Option<(String, i32)>, Trap> {
+    let mut nearest: Option<(String, i32)> = None;
+    for export in instance_info.instance.exports() {
+        let name = export.name().to_string();
+        // Names starting with `__` belong to the linker, and mutable globals such
+        // as `__stack_pointer` are no symbols.
+        let is_symbol = match export.into_global() {
+            Some(global) => {
+                !name.starts_with("__") && global.ty().mutability() == Mutability::Const
+            }
+            None => false,
+        };
+        if !is_symbol {
+            continue;
+        }
 
-            let memory = wasm_memory()?;
-            let id = unsafe { access_immutable_memory(&memory, id_ptr)? };
-            let dependency = MANAGER.with(|m| {
-                let mut manager = m.borrow_mut();
-                match manager.as_mut() {
-                    Some(manager) => manager.resolve_id(&caller_module, &id),
-                    None => unreachable!(),
-                }
-            })?;
-            let module_identifier = dependency.id.clone();
+        if let Some(symbol) = instance_data_address(instance_info, &name)? {
+            if symbol <= address
+                && nearest
+                    .as_ref()
+                    .map_or(true, |(_, closest)| symbol > *closest)
+            {
+                nearest = Some((name, symbol));
+            }
+        }
+    }
+    Ok(nearest)
+}
+
+/// Fills the `Dl_info` at `info`, which is `{ dli_fname, dli_fbase, dli_sname, dli_saddr }`.
+fn write_dl_info(
+    runtime: &Runtime,
+    info: i32,
+    module: &str,
+    base: u32,
+    symbol: Option<(&str, u32)>,
+) -> Result<(), Trap> {
+    let fname = runtime.guest_string(module)?;
+    let (sname, saddr) = match symbol {
+        Some((name, address)) => (runtime.guest_string(name)?, address),
+        None => (0, 0),
+    };
+    runtime.write_u32s(info, &[fname, base, sname, saddr])
+}
 
-            if let Some(index) = INSTANCES.with(|i| {
-                let instances = (*i).lock().unwrap();
-                for (i, instance_info) in instances.iter().enumerate() {
-                    if instance_info.name == module_identifier {
-                        return Some(i);
+/// `dladdr(addr, info)` fills the `Dl_info` at `info` for the data address `addr`: the
+/// owning module's name and `__memory_base`, and the nearest exported data symbol at or
+/// below `addr`. Returns 0 if no module owns `addr`.
+pub fn dladdr(store: &Store, runtime: &Rc<Runtime>) -> Func {
+    let runtime = Rc::downgrade(runtime);
+    Func::wrap(
+        &store,
+        move |_: Caller<'_>, address: i32, info: i32| -> Result<i32, Trap> {
+            let runtime = upgrade(&runtime)?;
+            let ret = || -> Result<i32, Trap> {
+                let found = {
+                    let instances = runtime.instances.borrow();
+                    match data_owner(&instances, address) {
+                        Some(owner) => {
+                            let instance_info = &instances[owner];
+                            let symbol = nearest_data_symbol(instance_info, address)?;
+                            Some((
+                                instance_info.name.clone(),
+                                instance_info.memory_base,
+                                symbol,
+                            ))
+                        }
+                        None => None,
                     }
-                }
-                None
-            }) {
-                return Ok((index + 1) as u32);
+                };
+                let (module, memory_base, symbol) = found.ok_or_else(|| {
+                    Trap::new(format!(
+                        "No module owns the address {:#x} (dladdr)",
+                        address
+                    ))
+                })?;
+
+                let symbol = symbol
+                    .as_ref()
+                    .map(|(name, address)| (name.as_str(), *address as u32));
+                write_dl_info(&runtime, info, &module, memory_base as u32, symbol)?;
+                Ok(1)
+            }();
+
+            if let Err(err) = ret {
+                error!("{}", &err.to_string());
+                runtime.write_error(&err.to_string());
+                return Ok(0);
             }
 
-            let handle_result = {
-                MANAGER.with(|m| {
-                    let mut manager = m.borrow_mut();
-                    let binaries = match manager.as_mut() {
-                        Some(manager) => manager.load(dependency),
-                        None => unreachable!(),
-                    };
-                    let handle = link_binaries(&binaries?, linker);
-                    handle
-                })
-            };
-            handle_result
-        });
+            ret
+        },
+    )
+}
 
-        if let Err(err) = ret {
-            error!("{}", &err.to_string());
-            write_error(&err.to_string());
-            return 0;
-        }
+/// `dladdr_function(func, info)` is `dladdr` for function pointers, which are table
+/// indices. The base is the owning module's `__table_base`, and the symbol is the name
+/// the function got its slot under through `GOT.func` or `dlsym`, if any.
+pub fn dladdr_function(store: &Store, runtime: &Rc<Runtime>) -> Func {
+    let runtime = Rc::downgrade(runtime);
+    Func::wrap(
+        &store,
+        move |_: Caller<'_>, index: i32, info: i32| -> Result<i32, Trap> {
+            let runtime = upgrade(&runtime)?;
+            let ret = || -> Result<i32, Trap> {
+                let index = index as u32;
+                match runtime.wasm_table()?.get(index) {
+                    Some(Val::FuncRef(Some(_))) => {}
+                    _ => {
+                        return Err(Trap::new(format!(
+                            "{} is no function pointer (dladdr_function)",
+                            index
+                        )))
+                    }
+                }
+
+                let (module, table_base) = {
+                    let instances = runtime.instances.borrow();
+                    function_owner(&instances, index).map(|owner| {
+                        let instance_info = &instances[owner];
+                        (
+                            instance_info.name.clone(),
+                            instance_info.table_base.unwrap_or(0),
+                        )
+                    })
+                }
+                .ok_or_else(|| Trap::new("No module is linked (dladdr_function)"))?;
+                let symbol = {
+                    let got = runtime.got_func.borrow();
+                    got.iter()
+                        .find(|(_, slot)| **slot == index)
+                        .map(|((_, name), _)| name.clone())
+                };
+
+                let symbol = symbol.as_deref().map(|name| (name, index));
+                write_dl_info(&runtime, info, &module, table_base, symbol)?;
+                Ok(1)
+            }();
+
+            if let Err(err) = ret {
+                error!("{}", &err.to_string());
+                runtime.write_error(&err.to_string());
+                return Ok(0);
+            }
 
-        ret.unwrap()
-    })
+            ret
+        },
+    )
 }
 
-pub fn dlsym(store: &Store) -> Func {
+/// `dlinfo(handle, info)` fills `{ name, memory_base, mem_size, table_base, table_size }`
+/// at `info` for the open module `handle`. Returns 0 on success and -1 otherwise.
+pub fn dlinfo(store: &Store, runtime: &Rc<Runtime>) -> Func {
+    let runtime = Rc::downgrade(runtime);
     Func::wrap(
         &store,
-        |_: Caller<'_>, handle: i32, function_name: i32| -> u32 {
-            let ret = || -> Result<u32, Trap> {
-                let memory = wasm_memory()?;
-                let function_name = unsafe { access_immutable_memory(&memory, function_name)? };
-                if let Some(i) = GOT_FUNC.with(|got_ref| -> Option<u32> {
-                    let got = got_ref.borrow();
-                    if let Some(index) = got.get(&function_name) {
-                        return Some(*index);
-                    }
-
-                    None
-                }) {
-                    return Ok(i);
+        move |_: Caller<'_>, handle: i32, info: i32| -> Result<i32, Trap> {
+            let runtime = upgrade(&runtime)?;
+            let ret = || -> Result<(), Trap> {
+                let (module, fields) = {
+                    let instances = runtime.instances.borrow();
+                    let instance_info = (handle as u32)
+                        .checked_sub(1)
+                        .and_then(|index| instances.get(index as usize))
+                        .filter(|instance_info| instance_info.is_open())
+                        .ok_or_else(|| {
+                            Trap::new(format!("No open module with handle {}", handle))
+                        })?;
+                    let (mem_size, table_size) = instance_info
+                        .dylink
+                        .as_ref()
+                        .map_or((0, 0), |dylink| (dylink.mem_size, dylink.table_size));
+                    (
+                        instance_info.name.clone(),
+                        [
+                            instance_info.memory_base as u32,
+                            mem_size,
+                            instance_info.table_base.unwrap_or(0),
+                            table_size,
+                        ],
+                    )
                 };
 
-                let table = wasm_table()?;
-                let side_instance = INSTANCES.with(|i| {
-                    let mut guard = (*i).lock().unwrap();
-                    match guard.get_mut((handle - 1) as usize) {
-                        Some(instance_info) => Ok(instance_info.instance.clone()),
-                        None => Err(Trap::new(format!("Instance with handle {} not found", handle))),
-                    }
-                })?;
-
-                let func = side_instance.get_func(&function_name).ok_or_else(|| {
-                    Trap::new("The item is either not a function or was not found (dlsym)")
-                })?;
-
-                GOT_FUNC.with(|got_ref| {
-                    let mut got = got_ref.borrow_mut();
-
-                    insert_function(table, func, function_name, &mut got)
-                })
+                let mut values = vec![runtime.guest_string(&module)?];
+                values.extend_from_slice(&fields);
+                runtime.write_u32s(info, &values)
             }();
 
             if let Err(err) = ret {
                 error!("{}", &err.to_string());
-                write_error(&err.to_string());
-                return 0;
+                runtime.write_error(&err.to_string());
+                return Ok(-1);
+            }
+
+            Ok(0)
+        },
+    )
+}
+
+/// Releases a handle returned by `dlopen`. The module is unloaded when the last
+/// handle to it and the last module needing it are closed. Returns 0 on success.
+pub fn dlclose(store: &Store, runtime: &Rc<Runtime>) -> Func {
+    let runtime = Rc::downgrade(runtime);
+    Func::wrap(
+        &store,
+        move |_: Caller<'_>, handle: i32| -> Result<i32, Trap> {
+            let runtime = upgrade(&runtime)?;
+            if let Err(err) = release(&runtime, handle as u32) {
+                error!("{}", &err.to_string());
+                runtime.write_error(&err.to_string());
+                return Ok(-1);
             }
 
-            ret.unwrap()
+            Ok(0)
         },
     )
 }
@@ -402,53 +1432,52 @@ pub fn get_platform_string() -> &'static str {
     }
 }
 
-pub fn fopen_and_read(store: &Store) -> Func {
-    Func::wrap(&store, |_: Caller<'_>, filename: i32, length: i32| -> u32 {
-        let ret = || -> Result<u32, Trap> {
-            let memory = wasm_memory()?;
-            let filename = unsafe { access_immutable_memory(&memory, filename)? };
-
-            let contents = std::fs::read(filename).map_err(|e| Trap::new(e.to_string()))?;
-            let pointer = wasm_malloc(contents.len() as u32)?;
-            unsafe {
-                &memory.data_unchecked_mut()[pointer as usize..pointer as usize + contents.len()]
-                    .copy_from_slice(&contents)
-            };
+pub fn fopen_and_read(store: &Store, runtime: &Rc<Runtime>) -> Func {
+    let runtime = Rc::downgrade(runtime);
+    Func::wrap(
+        &store,
+        move |_: Caller<'_>, filename: i32, length: i32| -> Result<u32, Trap> {
+            let runtime = upgrade(&runtime)?;
+            let ret = || -> Result<u32, Trap> {
+                let memory = runtime.wasm_memory()?;
+                let filename = unsafe { access_immutable_memory(&memory, filename)? };
+
+                let contents = std::fs::read(filename).map_err(|e| Trap::new(e.to_string()))?;
+                let pointer = runtime.wasm_malloc(contents.len() as u32)?;
+                unsafe {
+                    &memory.data_unchecked_mut()
+                        [pointer as usize..pointer as usize + contents.len()]
+                        .copy_from_slice(&contents)
+                };
 
-            let len = contents.len() as u32;
+                let len = contents.len() as u32;
 
-            unsafe {
-                memory.data_unchecked_mut()[length as usize..(length + 4) as usize]
-                    .copy_from_slice(&len.to_le_bytes());
-            }
+                unsafe {
+                    memory.data_unchecked_mut()[length as usize..(length + 4) as usize]
+                        .copy_from_slice(&len.to_le_bytes());
+                }
 
-            Ok(pointer)
-        }();
+                Ok(pointer)
+            }();
 
-        if let Err(err) = ret {
-            write_error(&err.to_string());
-            return 0;
-        }
+            if let Err(err) = ret {
+                runtime.write_error(&err.to_string());
+                return Ok(0);
+            }
 
-        ret.unwrap()
-    })
+            ret
+        },
+    )
 }
 
-pub fn write_file(store: &Store) -> Func {
+pub fn write_file(store: &Store, runtime: &Rc<Runtime>) -> Func {
+    let runtime = Rc::downgrade(runtime);
     Func::wrap(
         &store,
-        |_: Caller<'_>, filename: i32, buffer: i32, length: i32| -> u32 {
-            let ret = LINKER.with(|m| -> Result<u32, Trap> {
-                let mut guard = m.lock().unwrap();
-                let a = guard.as_mut();
-                let linker = a.unwrap();
-
-                let memory = linker
-                    .get_one_by_name("env", "memory")?
-                    .into_memory()
-                    .ok_or_else(|| {
-                        Trap::new("The external is not a func found in linker (dlsym)")
-                    })?;
+        move |_: Caller<'_>, filename: i32, buffer: i32, length: i32| -> Result<u32, Trap> {
+            let runtime = upgrade(&runtime)?;
+            let ret = || -> Result<u32, Trap> {
+                let memory = runtime.wasm_memory()?;
 
                 let data: &[u8] = unsafe {
                     &memory.data_unchecked()[buffer as usize..(buffer + length) as usize]
@@ -460,42 +1489,157 @@ pub fn write_file(store: &Store) -> Func {
                     .map_err(|e| Trap::new(e.to_string()))?;
 
                 return Ok(1);
-            });
+            }();
 
             if let Err(err) = ret {
-                write_error(&err.to_string());
-                return 0;
+                runtime.write_error(&err.to_string());
+                return Ok(0);
             }
 
-            return ret.unwrap();
+            return ret;
         },
     )
 }
 
-pub fn run_main() {
+/// Why a call into the guest failed.
+#[derive(Debug)]
+pub enum GuestError {
+    /// The call ran longer than the execution budget and was interrupted.
+    Timeout(Duration),
+    /// The guest trapped or the call could not be made.
+    Failed(anyhow::Error),
+}
+
+impl fmt::Display for GuestError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GuestError::Timeout(budget) => {
+                write!(f, "timeout: the guest ran longer than {:?}", budget)
+            }
+            GuestError::Failed(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for GuestError {}
+
+/// Lets each call into the guest through `call_guest` run for at most `budget`, or
+/// without a limit for `None`.
+pub fn set_execution_budget(runtime: &Runtime, budget: Option<Duration>) {
+    runtime.budget.replace(budget);
+}
+
+/// Calls `func` within the execution budget. Calls the guest makes back into itself
+/// through the host count against the budget of the outermost call.
+pub fn call_guest(
+    runtime: &Runtime,
+    func: &Func,
+    params: &[Val],
+) -> Result<Box<[Val]>, GuestError> {
+    let budget = match *runtime.budget.borrow() {
+        Some(budget) => budget,
+        None => return func.call(params).map_err(GuestError::Failed),
+    };
+    if runtime
+        .watchdog
+        .borrow()
+        .as_ref()
+        .is_some_and(Watchdog::is_armed)
+    {
+        return func.call(params).map_err(GuestError::Failed);
+    }
+
+    {
+        let mut watchdog = runtime.watchdog.borrow_mut();
+        if watchdog.is_none() {
+            let handle = runtime.interrupt.borrow_mut().take().ok_or_else(|| {
+                GuestError::Failed(anyhow::anyhow!(
+                    "The store of the runtime cannot be interrupted"
+                ))
+            })?;
+            *watchdog = Some(Watchdog::new(move || handle.interrupt()));
+        }
+        watchdog.as_ref().unwrap().arm(budget);
+    }
+    let result = func.call(params);
+    let timed_out = runtime
+        .watchdog
+        .borrow()
+        .as_ref()
+        .is_some_and(Watchdog::disarm);
+    // The budget ran out after the guest returned. The store keeps the interrupt until guest
+    // code runs again, which would make the next call fail.
+    if timed_out && result.is_ok() {
+        if let Err(err) = take_interrupt(runtime, func.store()) {
+            error!("The next call into the guest may be interrupted: {}", err);
+        }
+    }
+
+    match result {
+        Err(_) if timed_out => Err(GuestError::Timeout(budget)),
+        result => result.map_err(GuestError::Failed),
+    }
+}
+
+/// Calls `func`, the function `name` the toolchain generated in `module` to set it up or
+/// tear it down, such as its constructors, within the execution budget.
+fn call_generated(runtime: &Runtime, module: &str, name: &str, func: &Func) -> Result<(), Trap> {
+    call_guest(runtime, func, &[])
+        .map(drop)
+        .map_err(|err| Trap::new(format!("{} of {} failed: {}", name, module, err)))
+}
+
+/// Consumes an interrupt of `store` that no guest code ran into yet, by calling a
+/// function that does nothing and traps on it.
+fn take_interrupt(runtime: &Runtime, store: &Store) -> anyhow::Result<()> {
+    let sink = runtime.interrupt_sink.borrow().clone();
+    let sink = match sink {
+        Some(sink) => sink,
+        None => {
+            // Interrupts are checked at loop headers.
+            let module = Module::new(store.engine(), r#"(module (func (export "sink") (loop)))"#)?;
+            let sink = Instance::new(store, &module, &[])?
+                .get_func("sink")
+                .ok_or_else(|| anyhow::anyhow!("The interrupt sink exports no function"))?;
+            runtime.interrupt_sink.replace(Some(sink.clone()));
+            sink
+        }
+    };
+    // Traps exactly when an interrupt was pending.
+    let _ = sink.call(&[]);
+    Ok(())
+}
+
+pub fn run_main(runtime: &Runtime) {
     info!("Going to run the module");
-    MAIN.with(|entrypoint| -> Result<(), Trap> {
-        let mut entrypoint = entrypoint.borrow_mut();
-        let result = entrypoint.as_mut().unwrap().call(&[]);
-        if result.is_ok() {
-            info!("RESULT: {:#?}", result.unwrap());
-        } else {
-            let err = result.unwrap_err();
-            if let Some(trap) = err.downcast_ref::<Trap>() {
-                if let Some(status) = trap.i32_exit_status() {
-                    info!("RESULT: {:#?}", status);
-                    return Ok(());
-                }
+    // Cloned, so no borrow is held while the application runs.
+    let entrypoint = runtime.main.borrow().clone();
+    let result = call_guest(runtime, &entrypoint.unwrap(), &[]);
+    if result.is_ok() {
+        info!("RESULT: {:#?}", result.unwrap());
+    } else {
+        let err = match result.unwrap_err() {
+            GuestError::Timeout(budget) => {
+                error!("main ran longer than its budget of {:?}", budget);
+                return;
+            }
+            GuestError::Failed(err) => err,
+        };
+        if let Some(trap) = err.downcast_ref::<Trap>() {
+            if let Some(status) = trap.i32_exit_status() {
+                info!("RESULT: {:#?}", status);
+                return;
             }
-            info!("RESULT: {:#?}", err);
         }
-        Ok(())
-    })
-    .unwrap();
+        info!("RESULT: {:#?}", err);
+    }
 }
 
-pub fn create_linker(main_module_name: &str) -> Result<Linker, Trap> {
-    let store = Store::default();
+/// A linker for the application `runtime` runs, with the host functions defined.
+pub fn create_linker(runtime: &Rc<Runtime>, main_module_name: &str) -> Result<Linker, Trap> {
+    // Interruptable, so calls into the guest can be stopped once they run out of budget.
+    let store = Store::new(&Engine::new(Config::new().interruptable(true)));
+    runtime.interrupt.replace(Some(store.interrupt_handle()?));
     let mut linker = Linker::new(&store);
     linker.allow_shadowing(true);
 
@@ -514,12 +1658,21 @@ pub fn create_linker(main_module_name: &str) -> Result<Linker, Trap> {
     linker.define(
         "host",
         "dlopen",
-        dlopen(&store, main_module_name.to_string()),
+        dlopen(&store, runtime, main_module_name.to_string()),
+    )?;
+    linker.define(
+        "host",
+        "dlopen_with_flags",
+        dlopen_with_flags(&store, runtime, main_module_name.to_string()),
     )?;
-    linker.define("host", "dlsym", dlsym(&store))?;
-    linker.define("host", "dlerror", dlerror(&store))?;
-    linker.define("host", "fopen_and_read", fopen_and_read(&store))?;
-    linker.define("host", "write_file", write_file(&store))?;
+    linker.define("host", "dlsym", dlsym(&store, runtime))?;
+    linker.define("host", "dlclose", dlclose(&store, runtime))?;
+    linker.define("host", "dladdr", dladdr(&store, runtime))?;
+    linker.define("host", "dladdr_function", dladdr_function(&store, runtime))?;
+    linker.define("host", "dlinfo", dlinfo(&store, runtime))?;
+    linker.define("host", "dlerror", dlerror(&store, runtime))?;
+    linker.define("host", "fopen_and_read", fopen_and_read(&store, runtime))?;
+    linker.define("host", "write_file", write_file(&store, runtime))?;
 
     Ok(linker)
 }
@@ -542,6 +1695,7 @@ pub fn init_logging() {
 }
 
 pub fn initialize(
+    runtime: &Rc<Runtime>,
     main_metadata: &str,
     main_module_name: &str,
     cache_path: &str,
@@ -556,25 +1710,27 @@ pub fn initialize(
         &Some(format!("{}/cache", cache_path)),
     )?;
 
-    let main_module = Module::from_binary(store.engine(), &manager.load_main(main_module_name)?)?;
-    MANAGER.with(|m| {
-        let mut option_manager = m.borrow_mut();
-        *option_manager = Some(manager);
-    });
+    let main_binary = manager.load_main(main_module_name)?;
+    // Main modules only have a dylink section when they were linked against side modules.
+    let main_dylink = Dylink::check_dylink(&main_binary).ok();
+    let main_module = Module::from_binary(store.engine(), &main_binary)?;
 
     for import in main_module.imports() {
         if import.module() == "env" {
             if import.name() == "table" {
                 // Create table if one required
                 if let ExternType::Table(t) = import.ty() {
-                    let table_ty = TableType::new(ValType::FuncRef, t.limits().clone());
+                    let maximum = runtime.limits.borrow().table_maximum(t.limits().max());
+                    let limits = Limits::new(t.limits().min(), maximum);
+                    let table_ty = TableType::new(ValType::FuncRef, limits);
                     let table = Table::new(&store, table_ty, Val::FuncRef(None))?;
                     linker.define("env", "table", table)?;
                 }
             } else if import.name() == "memory" {
                 // Create memory if one required
                 if let ExternType::Memory(m) = import.ty() {
-                    let memory_ty = MemoryType::new(m.limits().clone());
+                    let maximum = runtime.limits.borrow().memory_maximum(m.limits().max());
+                    let memory_ty = MemoryType::new(Limits::new(m.limits().min(), maximum));
                     let memory = Memory::new(&store, memory_ty);
                     linker.define("env", "memory", memory)?;
                 }
@@ -583,50 +1739,173 @@ pub fn initialize(
     }
     info!("Should be initalizing,");
 
-    let main_instance = linker.instantiate(&main_module)?;
+    // A main module linked against side modules imports what its needed libraries provide,
+    // but they are linked after it. Its missing functions are stubs that look them up on their
+    // first call, and its GOT entries start out as 0 and are filled once the libraries are linked.
+    let mut got = vec![];
+    let mut imports = vec![];
+    for import in main_module.imports() {
+        let item = match linker.get_one_by_name(import.module(), import.name()) {
+            Ok(item) => item,
+            Err(err) => match (&main_dylink, import.module(), import.ty()) {
+                (Some(_), "env", ExternType::Func(ty)) => unresolved_function(
+                    &store,
+                    runtime,
+                    main_module_name,
+                    import.name(),
+                    ty,
+                    Some(vec![]),
+                )
+                .into(),
+                (Some(_), "GOT.func", _) | (Some(_), "GOT.mem", _) => {
+                    let global = Global::new(
+                        &store,
+                        GlobalType::new(ValType::I32, Mutability::Var),
+                        0.into(),
+                    )?;
+                    got.push((
+                        import.module().to_string(),
+                        import.name().to_string(),
+                        global.clone(),
+                    ));
+                    global.into()
+                }
+                _ => {
+                    return Err(Trap::new(format!(
+                        "Unable to link `{}` module: {}",
+                        main_module_name, err
+                    ))
+                    .into())
+                }
+            },
+        };
+        imports.push(item);
+    }
+    let main_instance = Instance::new(&store, &main_module, &imports)?;
     linker.instance(&main_module_name, &main_instance)?;
 
     let memory = main_instance
         .get_memory("memory")
         .ok_or(Trap::new("A memory is not exported (main)"))?;
-    MEMORY.with(|v| {
-        v.replace(Some(memory.clone()));
-    });
+    runtime.memory.replace(Some(memory.clone()));
+    // A memory the main module defines itself has no maximum from the limits, so at least
+    // its initial size has to be within them. Linking side modules never grows it past them.
+    runtime
+        .limits
+        .borrow()
+        .check_memory(memory.size() as u64)
+        .map_err(|e| Trap::new(format!("Cannot run {}: {}", main_module_name, e)))?;
 
     let table = main_instance
         .get_table("__indirect_function_table")
         .or(main_instance.get_table("table"))
         .ok_or(Trap::new("A table is not exported (main)"))?;
-    TABLE.with(|v| {
-        v.replace(Some(table.clone()));
-    });
+    runtime.table.replace(Some(table.clone()));
 
     let malloc = linker
         .get_one_by_name(main_module_name, "malloc")
         .or(Err(Trap::new("The malloc not exported (main)")))?
         .into_func()
         .ok_or(Trap::new("The malloc export is not a function (main)"))?;
-    MALLOC.with(|v| {
-        v.replace(Some(malloc.clone()));
-    });
+    runtime.malloc.replace(Some(malloc.clone()));
 
-    DLERROR.with(|error_ref| -> Result<(), Trap> {
-        let mut error = error_ref.borrow_mut();
+    // Without `free`, dlclose leaks the data of unloaded modules.
+    let free = linker
+        .get_one_by_name(main_module_name, "free")
+        .ok()
+        .and_then(|free| free.into_func());
+    runtime.free.replace(free);
+
+    let position = runtime.wasm_malloc(runtime.dlerror.borrow().size as u32)?;
+    {
+        let mut error = runtime.dlerror.borrow_mut();
         error.memory = Some(memory.clone());
-        error.position = wasm_malloc(error.size as u32)? as usize;
-        Ok(())
-    })?;
+        error.position = position as usize;
+    }
 
-    INSTANCES.with(|i| {
-        let mut instances = (*i).lock().unwrap();
-        instances.push(InstanceInfo {
-            name: main_module_name.to_string(),
-            instance: main_instance.clone(),
-            dylink: None,
-            memory_base: 0, // Any offsets are relative to 0 for main module
-            table_base: None,
-        });
+    runtime.instances.borrow_mut().push(InstanceInfo {
+        name: main_module_name.to_string(),
+        instance: main_instance.clone(),
+        dylink: None,
+        memory_base: 0, // Any offsets are relative to 0 for main module
+        table_base: None,
+        references: 1,
+        allocation: None,
+        needed: vec![],
+        function_slots: vec![],
+        global: true,
+        unbound: vec![],
     });
+    set_symbol_priority(runtime, main_module_name, MAIN_PRIORITY);
+    let exports = main_module
+        .exports()
+        .map(|export| {
+            let weak = main_dylink
+                .as_ref()
+                .is_some_and(|dylink| dylink.is_weak_export(export.name()));
+            (export.name().to_string(), weak)
+        })
+        .collect();
+    define_symbols(runtime, main_module_name, exports)?;
+
+    if let Some(dylink) = &main_dylink {
+        let needed = link_needed_dynlibs(
+            runtime,
+            &mut manager,
+            main_module_name,
+            dylink,
+            RTLD_NOW | RTLD_GLOBAL,
+            &mut HashMap::new(),
+            &mut vec![main_module_name.to_string()],
+            &mut linker,
+        )?;
+        runtime.instances.borrow_mut()[0].needed = needed;
+    }
+
+    if !got.is_empty() {
+        let weak_imports = manager.weak_imports(main_module_name);
+        let is_weak = |module: &str, name: &str| {
+            main_dylink
+                .as_ref()
+                .is_some_and(|dylink| dylink.is_weak_import(module, name))
+                || weak_imports.iter().any(|weak| weak == name)
+        };
+        for (module, name, global) in got {
+            let value = if module == "GOT.mem" {
+                match find_export(runtime, &name, &[], |instance_info| {
+                    instance_info.instance.get_global(&name)
+                }) {
+                    Some((owner, _)) => {
+                        instance_data_address(&runtime.instances.borrow()[owner], &name)?
+                    }
+                    None => None,
+                }
+            } else if let Some((owner, func)) = visible_function(runtime, &name, &[]) {
+                Some(function_slot(runtime, &table, Some(owner), &name, func)? as i32)
+            } else if let Some(func) = host_function(runtime, &linker, &name) {
+                Some(function_slot(runtime, &table, None, &name, func)? as i32)
+            } else {
+                None
+            };
+
+            match value {
+                Some(value) => global.set(Val::I32(value))?,
+                // Missing weak data and functions are at 0.
+                None if is_weak(&module, &name) => {}
+                None => return Err(Trap::new(format!("Couldn't find {}.{}", module, name)).into()),
+            }
+        }
+
+        // The relocations of the main module's data read its GOT, which is only complete now.
+        if let Some(apply_data_relocs) = main_instance.get_func("__wasm_apply_data_relocs") {
+            call_generated(
+                runtime,
+                main_module_name,
+                "__wasm_apply_data_relocs",
+                &apply_data_relocs,
+            )?;
+        }
+    }
 
     let entrypoint = linker.get_default(main_module_name).or_else(|err| {
         Err(Trap::new(format!(
@@ -635,12 +1914,599 @@ pub fn initialize(
         )))
     })?;
 
-    LINKER.with(|m| {
-        *m.lock().unwrap() = Some(linker);
-    });
+    runtime.manager.replace(Some(manager));
+    runtime.linker.replace(Some(linker));
+    runtime.main.replace(Some(entrypoint));
+    Ok(())
+}
 
-    MAIN.with(|rc| {
-        *rc.borrow_mut() = Some(entrypoint);
+#[test]
+fn call_guest_interrupts_only_calls_over_budget() -> anyhow::Result<()> {
+    let runtime = Runtime::new();
+    let linker = create_linker(&runtime, "main")?;
+    let module = Module::new(
+        linker.store().engine(),
+        r#"(module
+            (func (export "spin") (loop (br 0)))
+            (func (export "answer") (result i32) (loop) (i32.const 42)))"#,
+    )?;
+    let instance = Instance::new(linker.store(), &module, &[])?;
+    let spin = instance.get_func("spin").unwrap();
+    let answer = instance.get_func("answer").unwrap();
+    set_execution_budget(&runtime, Some(Duration::from_millis(50)));
+
+    match call_guest(&runtime, &spin, &[]) {
+        Err(GuestError::Timeout(budget)) => assert_eq!(budget, Duration::from_millis(50)),
+        other => panic!(
+            "expected a timeout, got {:?}",
+            other.map_err(|e| e.to_string())
+        ),
+    }
+    assert_eq!(call_guest(&runtime, &answer, &[])?[0].i32(), Some(42));
+
+    // As if the watchdog fired between the return of a call and disarming it.
+    linker.store().interrupt_handle()?.interrupt();
+    take_interrupt(&runtime, linker.store())?;
+    assert_eq!(call_guest(&runtime, &answer, &[])?[0].i32(), Some(42));
+
+    Ok(())
+}
+
+#[cfg(test)]
+const DYNAMIC_LINKING: &str = concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/../modules/public/dynamic_linking"
+);
+
+/// Serves the files `files` returns for the base URL they are served from, by their path,
+/// over HTTP for the rest of the test run. Returns the base URL.
+#[cfg(test)]
+fn serve(
+    files: impl FnOnce(&str) -> anyhow::Result<HashMap<String, Vec<u8>>>,
+) -> anyhow::Result<String> {
+    use std::io::{BufRead, BufReader, Write};
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0")?;
+    let base = format!("http://{}", listener.local_addr()?);
+    let files = files(&base)?;
+    std::thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let mut reader = BufReader::new(&stream);
+            let mut request = String::new();
+            if reader.read_line(&mut request).is_err() {
+                continue;
+            }
+            // Only GET requests are made, which end with their headers.
+            let mut header = String::new();
+            while reader.read_line(&mut header).is_ok_and(|read| read > 0)
+                && !header.trim().is_empty()
+            {
+                header.clear();
+            }
+
+            let path = request.split(' ').nth(1).unwrap_or_default();
+            let response = match files.get(path) {
+                Some(body) => [
+                    format!(
+                        "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                        body.len()
+                    )
+                    .into_bytes(),
+                    body.clone(),
+                ]
+                .concat(),
+                None => b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+                    .to_vec(),
+            };
+            let _ = (&stream).write_all(&response);
+        }
     });
+
+    Ok(base)
+}
+
+/// Serves the binaries of `modules/public/dynamic_linking`, with `patch` applied to the side
+/// module. It is served as `side` and as `weak_side`, whose metadata lists `weak_imports`.
+/// Returns the metadata of the main module.
+#[cfg(test)]
+fn serve_dynamic_linking(
+    patch: impl Fn(Vec<u8>) -> Vec<u8>,
+    weak_imports: &[&str],
+) -> anyhow::Result<String> {
+    let modules = std::path::Path::new(DYNAMIC_LINKING);
+    let dependency = |id: &str, base: &str| {
+        format!(
+            r#""{0}": {{ "id": "{0}", "loadTime": false, "location": "{1}/api" }}"#,
+            id, base
+        )
+    };
+
+    let base = serve(|base| {
+        let mut files = HashMap::new();
+        files.insert(
+            "/main.wasm".to_string(),
+            std::fs::read(modules.join("main.wasm"))?,
+        );
+        files.insert(
+            "/side.wasm".to_string(),
+            patch(std::fs::read(modules.join("side.wasm"))?),
+        );
+        for (id, weak) in &[("side", &[][..]), ("weak_side", weak_imports)] {
+            let metadata = format!(
+                r#"{{ "id": "{}", "attributes": [], "location": "{}/side.wasm", "dependencies": {{ {} }}, "weakImports": {:?} }}"#,
+                id,
+                base,
+                dependency("main", base),
+                weak
+            );
+            files.insert(format!("/api/{}", id), metadata.into_bytes());
+        }
+        Ok(files)
+    })?;
+
+    Ok(format!(
+        r#"{{ "id": "main", "attributes": [], "location": "{}/main.wasm", "dependencies": {{ {}, {} }} }}"#,
+        base,
+        dependency("side", &base),
+        dependency("weak_side", &base)
+    ))
+}
+
+/// Renames the function side imports from main, in `env` and `GOT.func`, so nothing
+/// provides it.
+#[cfg(test)]
+fn without_shared_function(side: Vec<u8>) -> Vec<u8> {
+    replace_all(
+        side,
+        b"statically_shared_function",
+        b"statically_shared_functioX",
+    )
+}
+
+#[cfg(test)]
+fn replace_all(mut binary: Vec<u8>, from: &[u8], to: &[u8]) -> Vec<u8> {
+    assert_eq!(from.len(), to.len());
+    let mut start = 0;
+    while let Some(offset) = binary[start..]
+        .windows(from.len())
+        .position(|window| window == from)
+    {
+        let at = start + offset;
+        binary[at..at + to.len()].copy_from_slice(to);
+        start = at + to.len();
+    }
+    binary
+}
+
+/// Defines the WASI functions the emscripten binaries import. What they write to any file
+/// descriptor ends up in the returned buffer.
+#[cfg(test)]
+fn define_wasi_stubs(linker: &mut Linker) -> anyhow::Result<Rc<RefCell<Vec<u8>>>> {
+    let output = Rc::new(RefCell::new(vec![]));
+    let written = output.clone();
+    linker.func(
+        "wasi_snapshot_preview1",
+        "fd_write",
+        move |caller: Caller<'_>,
+              _: i32,
+              iovs: i32,
+              iovs_len: i32,
+              nwritten: i32|
+              -> Result<i32, Trap> {
+            let memory = caller
+                .get_export("memory")
+                .and_then(Extern::into_memory)
+                .ok_or_else(|| Trap::new("fd_write needs the memory of its caller"))?;
+            let data = unsafe { memory.data_unchecked_mut() };
+            let u32_at = |data: &[u8], at: usize| {
+                u32::from_le_bytes([data[at], data[at + 1], data[at + 2], data[at + 3]]) as usize
+            };
+            let mut total = 0;
+            for iov in 0..iovs_len as usize {
+                let iov = iovs as usize + 8 * iov;
+                let (start, len) = (u32_at(data, iov), u32_at(data, iov + 4));
+                written
+                    .borrow_mut()
+                    .extend_from_slice(&data[start..start + len]);
+                total += len as u32;
+            }
+            data[nwritten as usize..nwritten as usize + 4].copy_from_slice(&total.to_le_bytes());
+            Ok(0)
+        },
+    )?;
+    linker.func(
+        "wasi_snapshot_preview1",
+        "proc_exit",
+        |status: i32| -> Result<(), Trap> { Err(Trap::i32_exit(status)) },
+    )?;
+    linker.func(
+        "wasi_snapshot_preview1",
+        "fd_fdstat_get",
+        |_: i32, _: i32| 0,
+    )?;
+    linker.func(
+        "wasi_snapshot_preview1",
+        "clock_res_get",
+        |_: i32, _: i32| 0,
+    )?;
+    linker.func(
+        "wasi_snapshot_preview1",
+        "clock_time_get",
+        |_: i32, _: i64, _: i32| 0,
+    )?;
+    Ok(output)
+}
+
+/// An initialized runtime with a cache of its own, whose host functions are called as the
+/// main module would call them.
+#[cfg(test)]
+struct TestRuntime {
+    runtime: Rc<Runtime>,
+    store: Store,
+    output: Rc<RefCell<Vec<u8>>>,
+    _cache: tempdir::TempDir,
+}
+
+#[cfg(test)]
+impl TestRuntime {
+    fn new(metadata: &str) -> anyhow::Result<Self> {
+        let cache = tempdir::TempDir::new("cache")?;
+        let runtime = Runtime::new();
+        let mut linker = create_linker(&runtime, "main")?;
+        let output = define_wasi_stubs(&mut linker)?;
+        let store = linker.store().clone();
+        initialize(
+            &runtime,
+            metadata,
+            "main",
+            &cache.path().to_string_lossy(),
+            vec![],
+            linker,
+            store.clone(),
+        )
+        .map_err(|e| anyhow::anyhow!("{}", e))?;
+
+        Ok(Self {
+            runtime,
+            store,
+            output,
+            _cache: cache,
+        })
+    }
+
+    /// What the guests wrote so far.
+    fn output(&self) -> anyhow::Result<String> {
+        Ok(String::from_utf8(self.output.borrow().clone())?)
+    }
+
+    /// Calls the host function `func` with i32 arguments and result.
+    fn call(&self, func: Func, args: &[i32]) -> anyhow::Result<i32> {
+        let args: Vec<Val> = args.iter().map(|arg| Val::I32(*arg)).collect();
+        func.call(&args)?[0]
+            .i32()
+            .ok_or_else(|| anyhow::anyhow!("The result is no i32"))
+    }
+
+    fn dlopen(&self, id: &str, flags: i32) -> anyhow::Result<i32> {
+        let id = self.runtime.guest_string(id)? as i32;
+        self.call(
+            dlopen_with_flags(&self.store, &self.runtime, "main".to_string()),
+            &[id, flags],
+        )
+    }
+
+    fn dlsym(&self, handle: i32, name: &str) -> anyhow::Result<i32> {
+        let name = self.runtime.guest_string(name)? as i32;
+        self.call(dlsym(&self.store, &self.runtime), &[handle, name])
+    }
+
+    fn dlclose(&self, handle: i32) -> anyhow::Result<i32> {
+        self.call(dlclose(&self.store, &self.runtime), &[handle])
+    }
+
+    fn dlerror(&self) -> anyhow::Result<Option<String>> {
+        match self.call(dlerror(&self.store, &self.runtime), &[])? {
+            0 => Ok(None),
+            message => self.read_string(message as u32).map(Some),
+        }
+    }
+
+    /// Space for the structs the `dladdr` functions and `dlinfo` fill.
+    fn info(&self) -> anyhow::Result<i32> {
+        Ok(self.runtime.wasm_malloc(20)? as i32)
+    }
+
+    fn read_u32s(&self, address: i32, count: usize) -> anyhow::Result<Vec<u32>> {
+        let memory = self.runtime.wasm_memory()?;
+        let data = unsafe { memory.data_unchecked() };
+        Ok((0..count)
+            .map(|i| {
+                let at = address as usize + 4 * i;
+                u32::from_le_bytes([data[at], data[at + 1], data[at + 2], data[at + 3]])
+            })
+            .collect())
+    }
+
+    fn read_string(&self, address: u32) -> anyhow::Result<String> {
+        Ok(unsafe { access_immutable_memory(&self.runtime.wasm_memory()?, address as i32)? })
+    }
+}
+
+#[test]
+fn main_module_runs_with_its_side_module() -> anyhow::Result<()> {
+    let test = TestRuntime::new(&serve_dynamic_linking(|side| side, &[])?)?;
+
+    // main opens side and calls its `run`, which opens main in turn.
+    run_main(&test.runtime);
+    let output = test.output()?;
+    for line in &[
+        "Side started!",
+        "Hello world!",
+        "pointer",
+        "2*5: 10",
+        "static",
+        "dynamic",
+        "Main ended!",
+    ] {
+        assert!(
+            output.lines().any(|l| l == *line),
+            "{:?} is missing from:\n{}",
+            line,
+            output
+        );
+    }
+
+    Ok(())
+}
+
+#[test]
+fn dlopen_with_rtld_noload_only_finds_open_modules() -> anyhow::Result<()> {
+    let test = TestRuntime::new(&serve_dynamic_linking(|side| side, &[])?)?;
+
+    assert_eq!(test.dlopen("side", RTLD_NOW | RTLD_NOLOAD)?, 0);
+    assert_eq!(test.dlerror()?, None);
+    let side = test.dlopen("side", RTLD_NOW)?;
+    assert_ne!(side, 0);
+    assert_eq!(test.dlopen("side", RTLD_NOW | RTLD_NOLOAD)?, side);
+
+    Ok(())
+}
+
+#[test]
+fn dlopen_requires_rtld_lazy_or_rtld_now() -> anyhow::Result<()> {
+    let test = TestRuntime::new(&serve_dynamic_linking(|side| side, &[])?)?;
+
+    assert_eq!(test.dlopen("side", RTLD_GLOBAL)?, 0);
+    let error = test.dlerror()?.unwrap_or_default();
+    assert!(error.contains("RTLD_LAZY"), "{}", error);
+
+    Ok(())
+}
+
+#[test]
+fn dlopen_with_rtld_global_promotes_a_local_module() -> anyhow::Result<()> {
+    let test = TestRuntime::new(&serve_dynamic_linking(|side| side, &[])?)?;
+
+    let side = test.dlopen("side", RTLD_LAZY | RTLD_LOCAL)?;
+    assert_ne!(side, 0);
+    assert_eq!(
+        test.dlopen("side", RTLD_NOW | RTLD_NOLOAD | RTLD_GLOBAL)?,
+        side
+    );
+
+    Ok(())
+}
+
+#[test]
+fn dlsym_returns_the_address_of_data() -> anyhow::Result<()> {
+    let test = TestRuntime::new(&serve_dynamic_linking(|side| side, &[])?)?;
+
+    // `shared` is main's `SharedStruct { const char *message; int value; }`.
+    let shared = test.dlsym(1, "shared")?;
+    let fields = test.read_u32s(shared, 2)?;
+    assert_eq!(test.read_string(fields[0])?, "Hello world!");
+    assert_eq!(fields[1], 100);
+
+    Ok(())
+}
+
+#[test]
+fn dlsym_only_searches_the_module_of_the_handle() -> anyhow::Result<()> {
+    let test = TestRuntime::new(&serve_dynamic_linking(|side| side, &[])?)?;
+
+    let side = test.dlopen("side", RTLD_NOW)?;
+    assert_eq!(test.dlsym(side, "shared")?, 0);
+    let error = test.dlerror()?.unwrap_or_default();
+    assert!(error.contains("shared was not found"), "{}", error);
+
+    Ok(())
+}
+
+#[test]
+fn dladdr_names_the_data_symbol_of_an_address() -> anyhow::Result<()> {
+    let test = TestRuntime::new(&serve_dynamic_linking(|side| side, &[])?)?;
+    let info = test.info()?;
+
+    let shared = test.dlsym(1, "shared")?;
+    assert_eq!(
+        test.call(dladdr(&test.store, &test.runtime), &[shared + 4, info])?,
+        1
+    );
+    let dl_info = test.read_u32s(info, 4)?;
+    assert_eq!(test.read_string(dl_info[0])?, "main");
+    assert_eq!(test.read_string(dl_info[2])?, "shared");
+    assert_eq!(dl_info[3], shared as u32);
+
+    Ok(())
+}
+
+#[test]
+fn dladdr_function_names_a_function_pointer() -> anyhow::Result<()> {
+    let test = TestRuntime::new(&serve_dynamic_linking(|side| side, &[])?)?;
+    let info = test.info()?;
+
+    let run = test.dlsym(test.dlopen("side", RTLD_NOW)?, "run")?;
+    assert_eq!(
+        test.call(dladdr_function(&test.store, &test.runtime), &[run, info])?,
+        1
+    );
+    let dl_info = test.read_u32s(info, 4)?;
+    assert_eq!(test.read_string(dl_info[0])?, "side");
+    assert_eq!(test.read_string(dl_info[2])?, "run");
+
+    Ok(())
+}
+
+#[test]
+fn dlinfo_describes_an_open_module() -> anyhow::Result<()> {
+    let test = TestRuntime::new(&serve_dynamic_linking(|side| side, &[])?)?;
+    let info = test.info()?;
+
+    let side = test.dlopen("side", RTLD_NOW)?;
+    assert_eq!(
+        test.call(dlinfo(&test.store, &test.runtime), &[side, info])?,
+        0
+    );
+    let module_info = test.read_u32s(info, 5)?;
+    assert_eq!(test.read_string(module_info[0])?, "side");
+    assert!(module_info[2] > 0);
+
+    Ok(())
+}
+
+#[test]
+fn dlclose_unloads_a_module_once_every_reference_is_closed() -> anyhow::Result<()> {
+    let test = TestRuntime::new(&serve_dynamic_linking(|side| side, &[])?)?;
+    let info = test.info()?;
+
+    let side = test.dlopen("side", RTLD_NOW)?;
+    assert_eq!(test.dlopen("side", RTLD_NOW)?, side);
+    let run = test.dlsym(side, "run")?;
+
+    assert_eq!(test.dlclose(side)?, 0);
+    assert_ne!(test.dlsym(side, "run")?, 0);
+    assert_eq!(test.dlclose(side)?, 0);
+    assert_eq!(test.dlsym(side, "run")?, 0);
+    assert_eq!(test.dlopen("side", RTLD_NOW | RTLD_NOLOAD)?, 0);
+    assert_eq!(
+        test.call(dladdr_function(&test.store, &test.runtime), &[run, info])?,
+        0
+    );
+
+    Ok(())
+}
+
+#[test]
+fn dlclose_fails_for_a_closed_handle() -> anyhow::Result<()> {
+    let test = TestRuntime::new(&serve_dynamic_linking(|side| side, &[])?)?;
+
+    let side = test.dlopen("side", RTLD_NOW)?;
+    assert_eq!(test.dlclose(side)?, 0);
+    assert_eq!(test.dlclose(side)?, -1);
+    assert!(test.dlerror()?.is_some());
+
+    Ok(())
+}
+
+#[test]
+fn reopened_module_gets_a_new_handle() -> anyhow::Result<()> {
+    let test = TestRuntime::new(&serve_dynamic_linking(|side| side, &[])?)?;
+
+    let side = test.dlopen("side", RTLD_NOW)?;
+    assert_eq!(test.dlclose(side)?, 0);
+    let reopened = test.dlopen("side", RTLD_NOW)?;
+    assert_ne!(reopened, 0);
+    assert_ne!(reopened, side);
+
+    Ok(())
+}
+
+#[test]
+fn dlopen_with_rtld_now_fails_on_a_missing_function() -> anyhow::Result<()> {
+    let test = TestRuntime::new(&serve_dynamic_linking(without_shared_function, &[])?)?;
+
+    assert_eq!(test.dlopen("side", RTLD_NOW)?, 0);
+    let error = test.dlerror()?.unwrap_or_default();
+    assert!(error.contains("statically_shared_functioX"), "{}", error);
+    // Nothing of the failed link is left open.
+    assert_eq!(test.dlopen("side", RTLD_NOW | RTLD_NOLOAD)?, 0);
+
+    Ok(())
+}
+
+#[test]
+fn dlopen_with_rtld_lazy_traps_once_a_missing_function_is_called() -> anyhow::Result<()> {
+    let test = TestRuntime::new(&serve_dynamic_linking(without_shared_function, &[])?)?;
+
+    let side = test.dlopen("side", RTLD_LAZY)?;
+    assert_ne!(side, 0);
+    // A function pointer, as the main module would call it.
+    let run = match test
+        .runtime
+        .wasm_table()?
+        .get(test.dlsym(side, "run")? as u32)
+    {
+        Some(Val::FuncRef(Some(run))) => run,
+        _ => anyhow::bail!("dlsym returned no function pointer for run"),
+    };
+    match call_guest(&test.runtime, &run, &[]) {
+        Err(GuestError::Failed(err)) => {
+            let message = format!("{:#}", err);
+            assert!(
+                message.contains("unresolved function env::statically_shared_functioX"),
+                "{}",
+                message
+            );
+        }
+        _ => anyhow::bail!("Calling the missing function did not trap"),
+    }
+
+    Ok(())
+}
+
+#[test]
+fn weak_imports_may_be_missing_with_rtld_now() -> anyhow::Result<()> {
+    let weak_imports = ["statically_shared_functioX"];
+    let test = TestRuntime::new(&serve_dynamic_linking(
+        without_shared_function,
+        &weak_imports,
+    )?)?;
+
+    assert_ne!(test.dlopen("weak_side", RTLD_NOW)?, 0);
+
+    Ok(())
+}
+
+#[test]
+fn runtimes_run_side_by_side_on_their_own_threads() -> anyhow::Result<()> {
+    let metadata = serve_dynamic_linking(|side| side, &[])?;
+
+    let threads: Vec<_> = (0..2)
+        .map(|_| {
+            let metadata = metadata.clone();
+            std::thread::spawn(move || -> Result<(String, i32), String> {
+                let run = || -> anyhow::Result<(String, i32)> {
+                    let test = TestRuntime::new(&metadata)?;
+                    run_main(&test.runtime);
+                    Ok((test.output()?, test.dlopen("side", RTLD_NOW | RTLD_NOLOAD)?))
+                };
+                run().map_err(|e| format!("{:#}", e))
+            })
+        })
+        .collect();
+
+    let mut handles = vec![];
+    for thread in threads {
+        let (output, side) = thread
+            .join()
+            .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+            .map_err(anyhow::Error::msg)?;
+        assert!(output.lines().any(|l| l == "Main ended!"), "{}", output);
+        handles.push(side);
+    }
+    // Each runtime numbers its modules itself.
+    assert_ne!(handles[0], 0);
+    assert_eq!(handles[0], handles[1]);
+
     Ok(())
 }
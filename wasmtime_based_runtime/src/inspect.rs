@@ -0,0 +1,558 @@
+use anyhow::{anyhow, Result};
+use dylink::{Dylink, DylinkError, Reader};
+use serde_derive::Serialize;
+use std::collections::BTreeMap;
+use std::fmt;
+
+const MAGIC_NUMBER: &[u8; 4] = b"\0asm";
+
+const CUSTOM_SECTION: u8 = 0;
+const TYPE_SECTION: u8 = 1;
+const IMPORT_SECTION: u8 = 2;
+const FUNCTION_SECTION: u8 = 3;
+const TABLE_SECTION: u8 = 4;
+const MEMORY_SECTION: u8 = 5;
+const GLOBAL_SECTION: u8 = 6;
+const EXPORT_SECTION: u8 = 7;
+
+const NAME_SECTION: &str = "name";
+const MODULE_NAME: u8 = 0;
+const FUNCTION_NAMES: u8 = 1;
+
+/// Everything the runtime can tell about a binary without instantiating it.
+#[derive(Serialize, Debug, Default)]
+pub struct ModuleInfo {
+    pub dylink: Option<Dylink>,
+    // Why the dylink section the binary starts with cannot be parsed.
+    pub dylink_error: Option<String>,
+    // Grouped by import module, e.g. `env`, `GOT.mem`, `GOT.func` or `host`.
+    pub imports: BTreeMap<String, Vec<Import>>,
+    pub exports: Vec<Export>,
+    // Tables and memories the module defines itself.
+    pub tables: Vec<TableType>,
+    pub memories: Vec<Limits>,
+    pub custom_sections: Vec<CustomSection>,
+    pub names: Option<Names>,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Import {
+    pub name: String,
+    #[serde(flatten)]
+    pub kind: ExternKind,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Export {
+    pub name: String,
+    pub index: u32,
+    #[serde(flatten)]
+    pub kind: ExternKind,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum ExternKind {
+    // The signature is missing for malformed type indices.
+    Func { signature: Option<String> },
+    Table(TableType),
+    Memory(Limits),
+    Global { value_type: String, mutable: bool },
+    Tag,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct TableType {
+    pub element: String,
+    pub limits: Limits,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Limits {
+    pub min: u64,
+    pub max: Option<u64>,
+    pub shared: bool,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct CustomSection {
+    pub name: String,
+    pub size: usize,
+}
+
+/// Contents of the `name` custom section.
+#[derive(Serialize, Debug, Default, Clone, PartialEq)]
+pub struct Names {
+    pub module: Option<String>,
+    pub functions: BTreeMap<u32, String>,
+}
+
+fn value_type(reader: &mut Reader) -> Result<String> {
+    let name = match reader.byte()? {
+        0x7f => "i32",
+        0x7e => "i64",
+        0x7d => "f32",
+        0x7c => "f64",
+        0x7b => "v128",
+        0x70 => "funcref",
+        0x6f => "externref",
+        other => return Err(anyhow!("Unknown value type {:#x}", other)),
+    };
+    Ok(name.to_string())
+}
+
+fn limits(reader: &mut Reader) -> Result<Limits> {
+    let flags = reader.byte()?;
+    let min = reader.leb128_u64()?;
+    let max = if flags & 0x1 != 0 {
+        Some(reader.leb128_u64()?)
+    } else {
+        None
+    };
+    Ok(Limits {
+        min,
+        max,
+        shared: flags & 0x2 != 0,
+    })
+}
+
+/// Skips a constant expression, such as the initializer of a global.
+fn skip_constant_expression(reader: &mut Reader) -> Result<()> {
+    loop {
+        match reader.byte()? {
+            0x0b => return Ok(()),
+            0x41 => {
+                reader.leb128_i32()?;
+            }
+            0x42 => {
+                reader.leb128_i64()?;
+            }
+            // global.get, ref.func
+            0x23 | 0xd2 => {
+                reader.leb128()?;
+            }
+            // f32.const, f64.const
+            0x43 => {
+                reader.bytes(4)?;
+            }
+            0x44 => {
+                reader.bytes(8)?;
+            }
+            // ref.null
+            0xd0 => {
+                reader.byte()?;
+            }
+            other => {
+                return Err(anyhow!(
+                    "Unsupported instruction {:#x} in a constant expression",
+                    other
+                ))
+            }
+        }
+    }
+}
+
+fn global_type(reader: &mut Reader) -> Result<ExternKind> {
+    Ok(ExternKind::Global {
+        value_type: value_type(reader)?,
+        mutable: reader.byte()? != 0,
+    })
+}
+
+fn table_type(reader: &mut Reader) -> Result<TableType> {
+    Ok(TableType {
+        element: value_type(reader)?,
+        limits: limits(reader)?,
+    })
+}
+
+fn signature(types: &[String], index: u32) -> Option<String> {
+    types.get(index as usize).cloned()
+}
+
+/// Describes `binary`, which has to be a WebAssembly module. Sections are only
+/// decoded as far as needed, so a binary that fails validation may still be inspected.
+pub fn inspect(binary: &[u8]) -> Result<ModuleInfo> {
+    if binary.len() < 8 || &binary[..4] != MAGIC_NUMBER {
+        return Err(anyhow!("Not a WebAssembly binary"));
+    }
+
+    let mut info = ModuleInfo::default();
+    match Dylink::check_dylink(binary) {
+        Ok(dylink) => info.dylink = Some(dylink),
+        Err(DylinkError::NotFirstSection) | Err(DylinkError::WrongSectionName(_)) => {}
+        Err(e) => info.dylink_error = Some(e.to_string()),
+    }
+    // Signatures of the type section, and the index spaces exports refer to,
+    // which start with the imports.
+    let mut types: Vec<String> = vec![];
+    let mut function_types: Vec<u32> = vec![];
+    let mut tables: Vec<TableType> = vec![];
+    let mut memories: Vec<Limits> = vec![];
+    let mut globals: Vec<ExternKind> = vec![];
+
+    let mut reader = Reader::new(&binary[8..]);
+    while !reader.is_empty() {
+        let id = reader.byte()?;
+        let size = reader.leb128()? as usize;
+        let mut section = reader.subsection(size)?;
+
+        match id {
+            CUSTOM_SECTION => {
+                let name = section.string()?.to_string();
+                let contents = section.remaining();
+                if name == NAME_SECTION {
+                    // A broken name section only loses the names.
+                    info.names = parse_names(contents).ok();
+                }
+                info.custom_sections.push(CustomSection {
+                    name,
+                    size: contents.len(),
+                });
+            }
+            TYPE_SECTION => {
+                for _ in 0..section.leb128()? {
+                    if section.byte()? != 0x60 {
+                        return Err(anyhow!("Unsupported type form in the type section"));
+                    }
+                    let mut params = vec![];
+                    for _ in 0..section.leb128()? {
+                        params.push(value_type(&mut section)?);
+                    }
+                    let mut results = vec![];
+                    for _ in 0..section.leb128()? {
+                        results.push(value_type(&mut section)?);
+                    }
+                    types.push(format!(
+                        "({}) -> ({})",
+                        params.join(", "),
+                        results.join(", ")
+                    ));
+                }
+            }
+            IMPORT_SECTION => {
+                for _ in 0..section.leb128()? {
+                    let module = section.string()?.to_string();
+                    let name = section.string()?.to_string();
+                    let kind = match section.byte()? {
+                        0 => {
+                            let index = section.leb128()?;
+                            function_types.push(index);
+                            ExternKind::Func {
+                                signature: signature(&types, index),
+                            }
+                        }
+                        1 => {
+                            tables.push(table_type(&mut section)?);
+                            ExternKind::Table(tables[tables.len() - 1].clone())
+                        }
+                        2 => {
+                            memories.push(limits(&mut section)?);
+                            ExternKind::Memory(memories[memories.len() - 1].clone())
+                        }
+                        3 => {
+                            globals.push(global_type(&mut section)?);
+                            globals[globals.len() - 1].clone()
+                        }
+                        4 => {
+                            section.byte()?;
+                            section.leb128()?;
+                            ExternKind::Tag
+                        }
+                        other => return Err(anyhow!("Unknown import kind {:#x}", other)),
+                    };
+                    info.imports
+                        .entry(module)
+                        .or_default()
+                        .push(Import { name, kind });
+                }
+            }
+            FUNCTION_SECTION => {
+                for _ in 0..section.leb128()? {
+                    function_types.push(section.leb128()?);
+                }
+            }
+            TABLE_SECTION => {
+                for _ in 0..section.leb128()? {
+                    let table = table_type(&mut section)?;
+                    tables.push(table.clone());
+                    info.tables.push(table);
+                }
+            }
+            MEMORY_SECTION => {
+                for _ in 0..section.leb128()? {
+                    let limits = limits(&mut section)?;
+                    memories.push(limits.clone());
+                    info.memories.push(limits);
+                }
+            }
+            GLOBAL_SECTION => {
+                for _ in 0..section.leb128()? {
+                    globals.push(global_type(&mut section)?);
+                    skip_constant_expression(&mut section)?;
+                }
+            }
+            EXPORT_SECTION => {
+                for _ in 0..section.leb128()? {
+                    let name = section.string()?.to_string();
+                    let kind = section.byte()?;
+                    let index = section.leb128()?;
+                    let missing = || anyhow!("Export {} refers to a missing item {}", name, index);
+                    let kind = match kind {
+                        0 => ExternKind::Func {
+                            signature: function_types
+                                .get(index as usize)
+                                .and_then(|ty| signature(&types, *ty)),
+                        },
+                        1 => ExternKind::Table(
+                            tables.get(index as usize).ok_or_else(missing)?.clone(),
+                        ),
+                        2 => ExternKind::Memory(
+                            memories.get(index as usize).ok_or_else(missing)?.clone(),
+                        ),
+                        3 => globals.get(index as usize).ok_or_else(missing)?.clone(),
+                        4 => ExternKind::Tag,
+                        other => return Err(anyhow!("Unknown export kind {:#x}", other)),
+                    };
+                    info.exports.push(Export { name, index, kind });
+                }
+            }
+            // Code and data say nothing about linking.
+            _ => {}
+        }
+    }
+
+    Ok(info)
+}
+
+fn parse_names(contents: &[u8]) -> Result<Names> {
+    let mut names = Names::default();
+    let mut reader = Reader::new(contents);
+    while !reader.is_empty() {
+        let id = reader.byte()?;
+        let size = reader.leb128()? as usize;
+        let mut subsection = reader.subsection(size)?;
+        match id {
+            MODULE_NAME => names.module = Some(subsection.string()?.to_string()),
+            FUNCTION_NAMES => {
+                for _ in 0..subsection.leb128()? {
+                    let index = subsection.leb128()?;
+                    names
+                        .functions
+                        .insert(index, subsection.string()?.to_string());
+                }
+            }
+            _ => {}
+        }
+    }
+
+    Ok(names)
+}
+
+impl fmt::Display for ExternKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExternKind::Func { signature } => {
+                write!(f, "func {}", signature.as_deref().unwrap_or("(?)"))
+            }
+            ExternKind::Table(table) => write!(f, "table {} {}", table.element, table.limits),
+            ExternKind::Memory(limits) => write!(f, "memory {}", limits),
+            ExternKind::Global {
+                value_type,
+                mutable,
+            } => {
+                write!(
+                    f,
+                    "global {}{}",
+                    if *mutable { "mut " } else { "" },
+                    value_type
+                )
+            }
+            ExternKind::Tag => write!(f, "tag"),
+        }
+    }
+}
+
+impl fmt::Display for Limits {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "min {}", self.min)?;
+        if let Some(max) = self.max {
+            write!(f, " max {}", max)?;
+        }
+        if self.shared {
+            write!(f, " shared")?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for ModuleInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(names) = &self.names {
+            if let Some(module) = &names.module {
+                writeln!(f, "Module name: {}", module)?;
+            }
+        }
+
+        match &self.dylink {
+            Some(dylink) => {
+                writeln!(f, "Dylink ({:?}):", dylink.format)?;
+                writeln!(
+                    f,
+                    "  mem_size {} (align 2^{})",
+                    dylink.mem_size, dylink.mem_align
+                )?;
+                writeln!(
+                    f,
+                    "  table_size {} (align 2^{})",
+                    dylink.table_size, dylink.table_align
+                )?;
+                for library in &dylink.needed_dynlibs {
+                    writeln!(f, "  needed {}", library)?;
+                }
+                for export in &dylink.export_info {
+                    writeln!(f, "  export {} flags {:#x}", export.name, export.flags)?;
+                }
+                for import in &dylink.import_info {
+                    writeln!(
+                        f,
+                        "  import {}::{} flags {:#x}",
+                        import.module, import.field, import.flags
+                    )?;
+                }
+                for path in &dylink.runtime_paths {
+                    writeln!(f, "  runtime path {}", path)?;
+                }
+            }
+            None => match &self.dylink_error {
+                Some(error) => writeln!(f, "Malformed dylink section: {}", error)?,
+                None => writeln!(f, "No dylink section")?,
+            },
+        }
+
+        writeln!(f, "Imports:")?;
+        for (module, imports) in &self.imports {
+            writeln!(f, "  {}:", module)?;
+            for import in imports {
+                writeln!(f, "    {} {}", import.name, import.kind)?;
+            }
+        }
+
+        writeln!(f, "Exports:")?;
+        for export in &self.exports {
+            writeln!(
+                f,
+                "  {} {} (index {})",
+                export.name, export.kind, export.index
+            )?;
+        }
+
+        for table in &self.tables {
+            writeln!(f, "Table: {} {}", table.element, table.limits)?;
+        }
+        for memory in &self.memories {
+            writeln!(f, "Memory: {}", memory)?;
+        }
+
+        writeln!(f, "Custom sections:")?;
+        for section in &self.custom_sections {
+            writeln!(f, "  {} ({} bytes)", section.name, section.size)?;
+        }
+
+        if let Some(names) = &self.names {
+            writeln!(f, "Function names: {}", names.functions.len())?;
+        }
+
+        Ok(())
+    }
+}
+
+#[test]
+fn inspect_side_module() -> Result<()> {
+    let info = inspect(include_bytes!(
+        "../../modules/public/dynamic_linking/side.wasm"
+    ))?;
+
+    assert!(info.dylink.is_some());
+    assert!(info.imports.contains_key("env"));
+    assert!(info.custom_sections.iter().any(|s| s.name == "dylink"));
+    assert!(!info.exports.is_empty());
+    assert!(info.to_string().contains("Imports:"));
+
+    Ok(())
+}
+
+#[test]
+fn inspect_handwritten_module() -> Result<()> {
+    #[rustfmt::skip]
+    let binary = [
+        0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00,
+        // type section: (i32) -> (i32)
+        0x01, 0x06, 0x01, 0x60, 0x01, 0x7f, 0x01, 0x7f,
+        // import section: GOT.mem.counter mut i32, env.f func 0
+        0x02, 0x1c, 0x02, 0x07, b'G', b'O', b'T', b'.', b'm', b'e', b'm', 0x07, b'c', b'o', b'u',
+        b'n', b't', b'e', b'r', 0x03, 0x7f, 0x01, 0x03, b'e', b'n', b'v', 0x01, b'f', 0x00, 0x00,
+        // function section: one function of type 0
+        0x03, 0x02, 0x01, 0x00,
+        // memory section: min 1 max 2
+        0x05, 0x04, 0x01, 0x01, 0x01, 0x02,
+        // export section: g = func 1
+        0x07, 0x05, 0x01, 0x01, b'g', 0x00, 0x01,
+        // name section: module "m", function 1 = "g"
+        0x00, 0x0f, 0x04, b'n', b'a', b'm', b'e', 0x00, 0x02, 0x01, b'm', 0x01, 0x04, 0x01, 0x01,
+        0x01, b'g',
+    ];
+    let info = inspect(&binary)?;
+
+    assert!(info.dylink.is_none());
+    assert!(info.dylink_error.is_none());
+    assert_eq!(
+        info.imports["GOT.mem"][0].kind,
+        ExternKind::Global {
+            value_type: "i32".to_string(),
+            mutable: true
+        }
+    );
+    assert_eq!(
+        info.imports["env"][0].kind,
+        ExternKind::Func {
+            signature: Some("(i32) -> (i32)".to_string())
+        }
+    );
+    assert_eq!(
+        info.exports[0].kind,
+        ExternKind::Func {
+            signature: Some("(i32) -> (i32)".to_string())
+        }
+    );
+    assert_eq!(
+        info.memories,
+        vec![Limits {
+            min: 1,
+            max: Some(2),
+            shared: false
+        }]
+    );
+    let names = info.names.unwrap();
+    assert_eq!(names.module.as_deref(), Some("m"));
+    assert_eq!(names.functions[&1], "g");
+
+    Ok(())
+}
+
+#[test]
+fn inspect_reports_malformed_dylink_section() -> Result<()> {
+    // A dylink.0 section whose mem info subsection is cut short.
+    #[rustfmt::skip]
+    let binary = [
+        0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00,
+        0x00, 0x0b, 0x08, b'd', b'y', b'l', b'i', b'n', b'k', b'.', b'0', 0x01, 0x05,
+    ];
+    let info = inspect(&binary)?;
+
+    assert!(info.dylink.is_none());
+    assert!(info.dylink_error.as_deref().unwrap().contains("truncated"));
+    assert!(info.to_string().contains("Malformed dylink section"));
+
+    Ok(())
+}
@@ -1,6 +1,9 @@
 pub mod global;
 pub mod host;
-pub mod leb128;
+pub mod inspect;
+pub mod limits;
+pub mod symbols;
+pub mod watchdog;
 
 #[allow(non_snake_case)]
 #[cfg(feature = "android_build")]
@@ -11,28 +14,54 @@ pub mod android {
 
     use jni::{
         objects::{JClass, JObject, JString},
+        sys::jlong,
         JNIEnv,
     };
+    use std::{rc::Rc, time::Duration};
 
-    use host::OurResult;
+    use global::Runtime;
+    use host::{GuestError, OurResult};
     use java::{CLASS, ENV};
 
     use super::*;
 
-    fn initialize(javaENV: &JNIEnv, callback: &JObject, cache_path: String) -> OurResult<()> {
+    // Android reports the app as not responding once the UI thread is blocked for 5 seconds.
+    const UI_CALL_BUDGET: Duration = Duration::from_secs(5);
+
+    /// The runtime behind a handle returned by `JNIInitializeRuntime`, which the Java side
+    /// keeps for as long as the application runs.
+    unsafe fn runtime<'a>(handle: jlong) -> &'a Rc<Runtime> {
+        &*(handle as *const Rc<Runtime>)
+    }
+
+    fn initialize(
+        javaENV: &JNIEnv,
+        callback: &JObject,
+        cache_path: String,
+    ) -> OurResult<Rc<Runtime>> {
         info!("getting metadata bytes");
         let metadata_bytes: Vec<u8> =
             java::get_metadata_bytes(&javaENV, &callback, "android_metadata.json")?;
         let metadata: String = String::from_utf8(metadata_bytes)?;
 
         info!("initializing");
-        let mut linker = host::create_linker("android")?;
+        let runtime = Runtime::new();
+        host::set_execution_budget(&runtime, Some(UI_CALL_BUDGET));
+        let mut linker = host::create_linker(&runtime, "android")?;
         let store = linker.store().clone();
 
-        linker.define("host", "CreateTextView", gui::create_text_view(&store))?;
-        linker.define("host", "ModifyTextView", gui::modify_text_view(&store))?;
+        linker.define(
+            "host",
+            "CreateTextView",
+            gui::create_text_view(&store, &runtime),
+        )?;
+        linker.define(
+            "host",
+            "ModifyTextView",
+            gui::modify_text_view(&store, &runtime),
+        )?;
         linker.define("host", "RemoveTextView", gui::remove_text_view(&store))?;
-        linker.define("host", "CreateButton", gui::create_button(&store))?;
+        linker.define("host", "CreateButton", gui::create_button(&store, &runtime))?;
         linker.define("host", "RegisterOnClick", gui::register_on_click(&store))?;
         linker.define("host", "RegisterOnTick", gui::register_on_tick(&store))?;
         linker.define("host", "CreateCanvas", gui::create_canvas(&store))?;
@@ -56,10 +85,11 @@ pub mod android {
             "CanvasDeleteBitmap",
             gui::canvas_delete_bitmap(&store),
         )?;
-        linker.define("host", "CreateText", gui::create_text(&store))?;
-        linker.define("host", "SetText", gui::set_text(&store))?;
+        linker.define("host", "CreateText", gui::create_text(&store, &runtime))?;
+        linker.define("host", "SetText", gui::set_text(&store, &runtime))?;
 
         host::initialize(
+            &runtime,
             &metadata,
             "android",
             &cache_path,
@@ -74,7 +104,7 @@ pub mod android {
         ENV.with(|rc| {
             *rc.borrow_mut() = Some(vm);
         });
-        Ok(())
+        Ok(runtime)
     }
 
     #[no_mangle]
@@ -83,7 +113,7 @@ pub mod android {
         _: JClass,
         callback: JObject,
         cacheDir: JString,
-    ) {
+    ) -> jlong {
         host::init_logging();
 
         std::panic::set_hook(Box::new(|panic_info| {
@@ -94,8 +124,12 @@ pub mod android {
             .expect("JNI error: Couldn't convert the data path string.")
             .into();
 
-        if let Err(e) = initialize(&javaENV, &callback, cache_path) {
-            javaENV.throw(e.to_string()).unwrap();
+        match initialize(&javaENV, &callback, cache_path) {
+            Ok(runtime) => Box::into_raw(Box::new(runtime)) as jlong,
+            Err(e) => {
+                javaENV.throw(e.to_string()).unwrap();
+                0
+            }
         }
     }
 
@@ -103,26 +137,31 @@ pub mod android {
     pub unsafe extern "C" fn Java_com_hy_wasmandroid_Wasm_JNIRunMainWASM(
         _javaENV: JNIEnv,
         _: JClass,
+        handle: jlong,
     ) {
-        host::run_main();
+        host::run_main(runtime(handle));
     }
 
     #[no_mangle]
     pub unsafe extern "C" fn Java_com_hy_wasmandroid_GuiContext_JNIButtonPress(
         _javaEnv: JNIEnv,
         _: JClass,
+        handle: jlong,
         id: i32,
     ) -> i32 {
-        use global::wasm_table;
         use gui::BUTTON_PRESSES;
         BUTTON_PRESSES.with(|rc| {
             let press_map = rc.borrow();
             match press_map.get(&id) {
                 Some(callback) => {
-                    let table = wasm_table().unwrap();
+                    let runtime = runtime(handle);
+                    let table = runtime.wasm_table().unwrap();
                     let callback_f = table.get(*callback).unwrap();
                     let f = callback_f.funcref().unwrap().unwrap();
-                    f.call(&[]).unwrap();
+                    if let Err(e) = host::call_guest(runtime, &f, &[]) {
+                        error!("OnClick handler of button {} failed: {}", id, e);
+                        return -1;
+                    }
                     0
                 }
                 None => -1,
@@ -131,17 +170,30 @@ pub mod android {
     }
 
     #[no_mangle]
-    pub unsafe extern "C" fn Java_com_hy_wasmandroid_Wasm_JNIOnTick(_javaENV: JNIEnv, _: JClass) {
-        use global::wasm_table;
+    pub unsafe extern "C" fn Java_com_hy_wasmandroid_Wasm_JNIOnTick(
+        _javaENV: JNIEnv,
+        _: JClass,
+        handle: jlong,
+    ) {
         use gui::ON_TICK_HANDLERS;
-        ON_TICK_HANDLERS.with(|rc| {
-            let vec = rc.borrow();
-            let table = wasm_table().unwrap();
-            for callback in vec.iter() {
-                let callback_f = table.get(*callback).unwrap();
-                let f = callback_f.funcref().unwrap().unwrap();
-                f.call(&[]).unwrap();
+        // Handlers are called without holding a borrow, as they may add handlers.
+        let handlers = ON_TICK_HANDLERS.with(|rc| rc.borrow().clone());
+        let runtime = runtime(handle);
+        let table = runtime.wasm_table().unwrap();
+        for callback in handlers {
+            let callback_f = table.get(callback).unwrap();
+            let f = callback_f.funcref().unwrap().unwrap();
+            match host::call_guest(runtime, &f, &[]) {
+                Ok(_) => {}
+                Err(e @ GuestError::Timeout(_)) => {
+                    // It would time out on every tick, so it is disabled.
+                    error!("Disabling OnTick handler {}: {}", callback, e);
+                    ON_TICK_HANDLERS.with(|rc| rc.borrow_mut().retain(|h| *h != callback));
+                }
+                Err(e) => {
+                    error!("OnTick handler {} failed: {}", callback, e);
+                }
             }
-        })
+        }
     }
 }
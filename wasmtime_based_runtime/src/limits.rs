@@ -0,0 +1,152 @@
+//! Caps on the resources one application may use, so that a side module cannot exhaust
+//! the device. They are checked when modules are linked, and a violation fails the
+//! `dlopen` with a message for `dlerror`.
+use std::fmt;
+
+/// Limits of one application. `None` leaves a resource unlimited, which is the default.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ResourceLimits {
+    /// Pages of 64 KiB the shared linear memory may grow to.
+    pub max_memory_pages: Option<u32>,
+    /// Slots the shared table may grow to.
+    pub max_table_size: Option<u32>,
+    /// Bytes of data, the `mem_size` of its dylink section, of a single side module.
+    pub max_module_data: Option<u32>,
+    /// Side modules that may be open at the same time.
+    pub max_modules: Option<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LimitError {
+    Memory { pages: u64, limit: u32 },
+    Table { size: u64, limit: u32 },
+    ModuleData { size: u32, limit: u32 },
+    Modules { limit: usize },
+}
+
+impl fmt::Display for LimitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LimitError::Memory { pages, limit } => write!(
+                f,
+                "The memory would grow to {} pages, the limit is {}",
+                pages, limit
+            ),
+            LimitError::Table { size, limit } => write!(
+                f,
+                "The table would grow to {} slots, the limit is {}",
+                size, limit
+            ),
+            LimitError::ModuleData { size, limit } => write!(
+                f,
+                "The module needs {} bytes of data, the limit is {}",
+                size, limit
+            ),
+            LimitError::Modules { limit } => {
+                write!(f, "{} side modules are open, which is the limit", limit)
+            }
+        }
+    }
+}
+
+impl std::error::Error for LimitError {}
+
+impl ResourceLimits {
+    pub fn check_memory(&self, pages: u64) -> Result<(), LimitError> {
+        match self.max_memory_pages {
+            Some(limit) if pages > limit as u64 => Err(LimitError::Memory { pages, limit }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Checks growing a table of `size` slots by `delta`.
+    pub fn check_table(&self, size: u32, delta: u32) -> Result<(), LimitError> {
+        let size = size as u64 + delta as u64;
+        match self.max_table_size {
+            Some(limit) if size > limit as u64 => Err(LimitError::Table { size, limit }),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn check_module_data(&self, size: u32) -> Result<(), LimitError> {
+        match self.max_module_data {
+            Some(limit) if size > limit => Err(LimitError::ModuleData { size, limit }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Checks opening another side module while `open` of them are open.
+    pub fn check_modules(&self, open: usize) -> Result<(), LimitError> {
+        match self.max_modules {
+            Some(limit) if open >= limit => Err(LimitError::Modules { limit }),
+            _ => Ok(()),
+        }
+    }
+
+    /// The maximum for a memory the runtime creates, given the `maximum` the module asks for.
+    pub fn memory_maximum(&self, maximum: Option<u32>) -> Option<u32> {
+        lower(maximum, self.max_memory_pages)
+    }
+
+    /// The maximum for a table the runtime creates, given the `maximum` the module asks for.
+    pub fn table_maximum(&self, maximum: Option<u32>) -> Option<u32> {
+        lower(maximum, self.max_table_size)
+    }
+}
+
+fn lower(a: Option<u32>, b: Option<u32>) -> Option<u32> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
+    }
+}
+
+#[test]
+fn unlimited_by_default() {
+    let limits = ResourceLimits::default();
+    assert_eq!(limits.check_memory(u64::MAX), Ok(()));
+    assert_eq!(limits.check_table(u32::MAX, u32::MAX), Ok(()));
+    assert_eq!(limits.check_module_data(u32::MAX), Ok(()));
+    assert_eq!(limits.check_modules(usize::MAX), Ok(()));
+    assert_eq!(limits.memory_maximum(None), None);
+    assert_eq!(limits.table_maximum(Some(10)), Some(10));
+}
+
+#[test]
+fn limits_are_inclusive() {
+    let limits = ResourceLimits {
+        max_memory_pages: Some(256),
+        max_table_size: Some(100),
+        max_module_data: Some(4096),
+        max_modules: Some(2),
+    };
+
+    assert_eq!(limits.check_memory(256), Ok(()));
+    assert_eq!(
+        limits.check_memory(257),
+        Err(LimitError::Memory {
+            pages: 257,
+            limit: 256
+        })
+    );
+    assert_eq!(limits.check_table(90, 10), Ok(()));
+    // The sum is not allowed to wrap around.
+    assert_eq!(
+        limits.check_table(u32::MAX, 2),
+        Err(LimitError::Table {
+            size: u32::MAX as u64 + 2,
+            limit: 100
+        })
+    );
+    assert_eq!(limits.check_module_data(4096), Ok(()));
+    assert!(limits.check_module_data(4097).is_err());
+    assert_eq!(limits.check_modules(1), Ok(()));
+    assert_eq!(
+        limits.check_modules(2),
+        Err(LimitError::Modules { limit: 2 })
+    );
+
+    assert_eq!(limits.memory_maximum(None), Some(256));
+    assert_eq!(limits.memory_maximum(Some(16)), Some(16));
+    assert_eq!(limits.table_maximum(Some(1000)), Some(100));
+}
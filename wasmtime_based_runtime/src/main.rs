@@ -1,8 +1,15 @@
 mod global;
 mod host;
-mod leb128;
+mod inspect;
+mod limits;
+mod symbols;
+mod watchdog;
 
+use dylink::{Dylink, DylinkError, DylinkFormat};
+use global::Runtime;
+use limits::ResourceLimits;
 use std::fs;
+use std::time::Duration;
 use wasmtime::*;
 
 fn main() -> Result<(), Trap> {
@@ -10,16 +17,38 @@ fn main() -> Result<(), Trap> {
 
     let args: Vec<String> = std::env::args().collect();
     let mut metafile_path = "./metafile.json";
+    let runtime = Runtime::new();
     if args.len() >= 2 {
         if args[1].trim() == "--exit" {
             return Ok(());
         }
-        metafile_path = &args[1];
+        if args[1].trim() == "inspect" {
+            return inspect_command(&args[2..]);
+        }
+        if args[1].trim() == "dylink" {
+            return dylink_command(&args[2..]);
+        }
+        if args[1..].iter().any(|a| a == "--strict-symbols") {
+            host::set_strict_symbols(&runtime, true);
+        }
+        host::set_limits(&runtime, limits_from_args(&args[1..])?);
+        if let Some(ms) = args[1..]
+            .iter()
+            .find_map(|a| a.strip_prefix("--execution-budget-ms="))
+        {
+            let ms = ms
+                .parse::<u64>()
+                .map_err(|e| Trap::new(format!("--execution-budget-ms {}: {}", ms, e)))?;
+            host::set_execution_budget(&runtime, Some(Duration::from_millis(ms)));
+        }
+        if let Some(path) = args[1..].iter().find(|a| !a.starts_with("--")) {
+            metafile_path = path;
+        }
     }
 
     let main_module_id = "main";
 
-    let linker = host::create_linker(main_module_id)?;
+    let linker = host::create_linker(&runtime, main_module_id)?;
     let store = linker.store().clone();
 
     let metadata = fs::read_to_string(metafile_path).map_err(|e| Trap::new(e.to_string()))?;
@@ -44,6 +73,7 @@ fn main() -> Result<(), Trap> {
     }
 
     host::initialize(
+        &runtime,
         &metadata,
         main_module_id,
         "./cache",
@@ -52,6 +82,119 @@ fn main() -> Result<(), Trap> {
         store,
     )
     .expect("initialization failed");
-    host::run_main();
+    host::run_main(&runtime);
+    Ok(())
+}
+
+/// Limits given as `--max-memory-pages=<n>`, `--max-table-size=<n>`,
+/// `--max-module-data=<n>` and `--max-modules=<n>`.
+fn limits_from_args(args: &[String]) -> Result<ResourceLimits, Trap> {
+    let mut limits = ResourceLimits::default();
+    for arg in args {
+        let (option, value) = match arg.find('=') {
+            Some(position) if arg.starts_with("--max-") => (&arg[..position], &arg[position + 1..]),
+            _ => continue,
+        };
+        let number = || {
+            value
+                .parse::<u32>()
+                .map_err(|e| Trap::new(format!("{} {}: {}", option, value, e)))
+        };
+        match option {
+            "--max-memory-pages" => limits.max_memory_pages = Some(number()?),
+            "--max-table-size" => limits.max_table_size = Some(number()?),
+            "--max-module-data" => limits.max_module_data = Some(number()?),
+            "--max-modules" => limits.max_modules = Some(number()? as usize),
+            _ => return Err(Trap::new(format!("Unknown option {}", option))),
+        }
+    }
+    Ok(limits)
+}
+
+/// `runtime-binary inspect <file.wasm> [--json]` describes a module without running it.
+fn inspect_command(args: &[String]) -> Result<(), Trap> {
+    let json = args.iter().any(|a| a == "--json");
+    let path = args
+        .iter()
+        .find(|a| !a.starts_with("--"))
+        .ok_or_else(|| Trap::new("Usage: runtime-binary inspect <file.wasm> [--json]"))?;
+
+    let binary = fs::read(path).map_err(|e| Trap::new(format!("{}: {}", path, e)))?;
+    let info = inspect::inspect(&binary).map_err(|e| Trap::new(format!("{}: {}", path, e)))?;
+    if json {
+        let json = serde_json::to_string_pretty(&info).map_err(|e| Trap::new(e.to_string()))?;
+        println!("{}", json);
+    } else {
+        print!("{}", info);
+    }
+
+    Ok(())
+}
+
+const DYLINK_USAGE: &str = "Usage: runtime-binary dylink <file.wasm> [--output <file.wasm>] [--format dylink|dylink.0] \
+[--mem-size <n>] [--mem-align <n>] [--table-size <n>] [--table-align <n>] [--add-needed <lib>] [--remove-needed <lib>]";
+
+/// `runtime-binary dylink <file.wasm> [options]` adds, changes or converts the dylink section
+/// of a side module in place, or writes the result to `--output`.
+fn dylink_command(args: &[String]) -> Result<(), Trap> {
+    let path = args.first().ok_or_else(|| Trap::new(DYLINK_USAGE))?;
+    let binary = fs::read(path).map_err(|e| Trap::new(format!("{}: {}", path, e)))?;
+    let mut dylink = match Dylink::check_dylink(&binary) {
+        Ok(dylink) => dylink,
+        // Not a side module yet, so emit a new section.
+        Err(DylinkError::NotFirstSection) | Err(DylinkError::WrongSectionName(_)) => {
+            Dylink::new(DylinkFormat::V0)
+        }
+        Err(e) => return Err(Trap::new(format!("{}: {}", path, e))),
+    };
+
+    let mut output = path;
+    let mut options = args[1..].iter();
+    while let Some(option) = options.next() {
+        let value = options
+            .next()
+            .ok_or_else(|| Trap::new(format!("{} needs a value\n{}", option, DYLINK_USAGE)))?;
+        let number = || {
+            value
+                .parse::<u32>()
+                .map_err(|e| Trap::new(format!("{} {}: {}", option, value, e)))
+        };
+        match option.as_str() {
+            "--output" => output = value,
+            "--format" => {
+                dylink.format = match value.as_str() {
+                    "dylink" => DylinkFormat::Legacy,
+                    "dylink.0" => DylinkFormat::V0,
+                    _ => {
+                        return Err(Trap::new(format!(
+                            "Unknown format {}\n{}",
+                            value, DYLINK_USAGE
+                        )))
+                    }
+                }
+            }
+            "--mem-size" => dylink.mem_size = number()?,
+            "--mem-align" => dylink.mem_align = number()?,
+            "--table-size" => dylink.table_size = number()?,
+            "--table-align" => dylink.table_align = number()?,
+            "--add-needed" => {
+                if !dylink.needed_dynlibs.contains(value) {
+                    dylink.needed_dynlibs.push(value.clone());
+                }
+            }
+            "--remove-needed" => dylink.needed_dynlibs.retain(|library| library != value),
+            _ => {
+                return Err(Trap::new(format!(
+                    "Unknown option {}\n{}",
+                    option, DYLINK_USAGE
+                )))
+            }
+        }
+    }
+
+    let rewritten = dylink
+        .write_dylink(&binary)
+        .map_err(|e| Trap::new(format!("{}: {}", path, e)))?;
+    fs::write(output, rewritten).map_err(|e| Trap::new(format!("{}: {}", output, e)))?;
     Ok(())
 }
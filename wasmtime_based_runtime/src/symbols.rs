@@ -0,0 +1,270 @@
+//! Which module's export an import resolves to when several modules export the same name.
+//!
+//! Strong definitions come before weak ones. Among those, modules with a higher priority
+//! come first, the main module highest of all, and then modules in the order they were loaded.
+use std::collections::HashMap;
+use std::fmt;
+
+/// Priority of the main module, whose exports win over those of every side module.
+pub const MAIN_PRIORITY: i32 = i32::MAX;
+
+#[derive(Debug, Clone)]
+struct Definition {
+    module: String,
+    weak: bool,
+    priority: i32,
+    order: u64,
+}
+
+/// A definition of `name` by `hidden` that imports no longer resolve to, because
+/// `winner` defines it as well and takes precedence.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Shadowed {
+    pub name: String,
+    pub winner: String,
+    pub hidden: String,
+}
+
+impl fmt::Display for Shadowed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} of {} is shadowed by {}",
+            self.name, self.hidden, self.winner
+        )
+    }
+}
+
+/// Strong exports of `module` that other modules already define strongly, as
+/// (name, other module) pairs. Strict mode rejects such modules.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DuplicateSymbols {
+    pub module: String,
+    pub duplicates: Vec<(String, String)>,
+}
+
+impl fmt::Display for DuplicateSymbols {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} defines symbols that are already defined:",
+            self.module
+        )?;
+        for (name, other) in &self.duplicates {
+            write!(f, " {} (by {})", name, other)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for DuplicateSymbols {}
+
+/// Names starting with `__`, such as `__wasm_call_ctors`, and `_emscripten_tls_init` are
+/// defined by the toolchain in every module, so defining them again is neither reported
+/// nor rejected.
+fn is_reserved(name: &str) -> bool {
+    name.starts_with("__") || name == "_emscripten_tls_init"
+}
+
+#[derive(Debug, Default)]
+pub struct SymbolTable {
+    // Definitions of every name, the preferred first.
+    definitions: HashMap<String, Vec<Definition>>,
+    priorities: HashMap<String, i32>,
+    strict: bool,
+    loaded: u64,
+}
+
+impl SymbolTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// In strict mode, `define` rejects modules with strong exports that an open module
+    /// already defines strongly.
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+
+    /// Exports of `module` win over those of modules with a lower priority, which is 0 unless
+    /// set. Applies to the definitions `module` makes from now on.
+    pub fn set_priority(&mut self, module: &str, priority: i32) {
+        self.priorities.insert(module.to_string(), priority);
+    }
+
+    /// Records the `exports` of `module`, each with whether it is weak, replacing those it
+    /// defined before. Returns the definitions that are shadowed from now on and involve `module`.
+    pub fn define(
+        &mut self,
+        module: &str,
+        exports: Vec<(String, bool)>,
+    ) -> Result<Vec<Shadowed>, DuplicateSymbols> {
+        self.remove(module);
+
+        if self.strict {
+            let duplicates: Vec<(String, String)> = exports
+                .iter()
+                .filter(|(name, weak)| !weak && !is_reserved(name))
+                .filter_map(|(name, _)| {
+                    let definitions = self.definitions.get(name)?;
+                    let strong = definitions.iter().find(|definition| !definition.weak)?;
+                    Some((name.clone(), strong.module.clone()))
+                })
+                .collect();
+            if !duplicates.is_empty() {
+                return Err(DuplicateSymbols {
+                    module: module.to_string(),
+                    duplicates,
+                });
+            }
+        }
+
+        let priority = self.priorities.get(module).cloned().unwrap_or(0);
+        self.loaded += 1;
+        let mut shadowed = vec![];
+        for (name, weak) in exports {
+            let definitions = self.definitions.entry(name.clone()).or_default();
+            definitions.push(Definition {
+                module: module.to_string(),
+                weak,
+                priority,
+                order: self.loaded,
+            });
+            definitions.sort_by_key(|definition| {
+                (
+                    definition.weak,
+                    std::cmp::Reverse(definition.priority),
+                    definition.order,
+                )
+            });
+
+            if is_reserved(&name) || definitions.len() < 2 {
+                continue;
+            }
+            let winner = &definitions[0].module;
+            for definition in &definitions[1..] {
+                if winner == module || definition.module == module {
+                    shadowed.push(Shadowed {
+                        name: name.clone(),
+                        winner: winner.clone(),
+                        hidden: definition.module.clone(),
+                    });
+                }
+            }
+        }
+
+        Ok(shadowed)
+    }
+
+    /// Forgets the definitions of `module`, e.g. because it was unloaded.
+    pub fn remove(&mut self, module: &str) {
+        for definitions in self.definitions.values_mut() {
+            definitions.retain(|definition| definition.module != module);
+        }
+        self.definitions
+            .retain(|_, definitions| !definitions.is_empty());
+    }
+
+    /// Modules defining `name`, the preferred first.
+    pub fn providers<'a>(&'a self, name: &str) -> impl Iterator<Item = &'a str> {
+        self.definitions
+            .get(name)
+            .into_iter()
+            .flatten()
+            .map(|definition| definition.module.as_str())
+    }
+}
+
+#[cfg(test)]
+fn exports(names: &[&str]) -> Vec<(String, bool)> {
+    names.iter().map(|name| (name.to_string(), false)).collect()
+}
+
+#[test]
+fn main_and_load_order_decide() -> Result<(), DuplicateSymbols> {
+    let mut symbols = SymbolTable::new();
+    symbols.set_priority("main", MAIN_PRIORITY);
+    symbols.define("side", exports(&["foo", "bar"]))?;
+    let shadowed = symbols.define("main", exports(&["foo"]))?;
+    assert_eq!(
+        shadowed,
+        vec![Shadowed {
+            name: "foo".to_string(),
+            winner: "main".to_string(),
+            hidden: "side".to_string(),
+        }]
+    );
+
+    let shadowed = symbols.define("other", exports(&["bar", "__wasm_call_ctors"]))?;
+    assert_eq!(shadowed.len(), 1);
+    assert_eq!(shadowed[0].winner, "side");
+    assert_eq!(
+        symbols.providers("foo").collect::<Vec<_>>(),
+        vec!["main", "side"]
+    );
+    assert_eq!(
+        symbols.providers("bar").collect::<Vec<_>>(),
+        vec!["side", "other"]
+    );
+
+    symbols.remove("side");
+    assert_eq!(symbols.providers("bar").collect::<Vec<_>>(), vec!["other"]);
+    assert_eq!(symbols.providers("missing").count(), 0);
+
+    Ok(())
+}
+
+#[test]
+fn priorities_and_weak_definitions() -> Result<(), DuplicateSymbols> {
+    let mut symbols = SymbolTable::new();
+    symbols.define(
+        "first",
+        vec![("foo".to_string(), true), ("bar".to_string(), false)],
+    )?;
+    symbols.set_priority("preferred", 10);
+    symbols.define("preferred", exports(&["bar"]))?;
+    symbols.define("last", exports(&["foo"]))?;
+
+    // A strong definition wins over an earlier weak one.
+    assert_eq!(
+        symbols.providers("foo").collect::<Vec<_>>(),
+        vec!["last", "first"]
+    );
+    assert_eq!(
+        symbols.providers("bar").collect::<Vec<_>>(),
+        vec!["preferred", "first"]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn strict_mode_rejects_duplicate_strong_definitions() -> Result<(), DuplicateSymbols> {
+    let mut symbols = SymbolTable::new();
+    symbols.set_strict(true);
+    symbols.define(
+        "side",
+        vec![("foo".to_string(), false), ("weak".to_string(), true)],
+    )?;
+
+    let error = symbols
+        .define(
+            "other",
+            exports(&["foo", "__post_instantiate", "_emscripten_tls_init"]),
+        )
+        .unwrap_err();
+    assert_eq!(
+        error.duplicates,
+        vec![("foo".to_string(), "side".to_string())]
+    );
+    assert_eq!(symbols.providers("foo").collect::<Vec<_>>(), vec!["side"]);
+
+    // Weak definitions and redefinitions by the same module are allowed.
+    symbols.define(
+        "other",
+        exports(&["weak", "__post_instantiate", "_emscripten_tls_init"]),
+    )?;
+    symbols.define("side", exports(&["foo"]))?;
+
+    Ok(())
+}
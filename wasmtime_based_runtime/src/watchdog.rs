@@ -0,0 +1,107 @@
+//! A thread that interrupts guest code which runs past its execution budget.
+//!
+//! The thread is started once and waits for `arm`, so calls into the guest, such as
+//! every `OnTick`, do not each start a thread of their own.
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+#[derive(Default)]
+struct State {
+    deadline: Option<Instant>,
+    fired: bool,
+    shutdown: bool,
+}
+
+pub struct Watchdog {
+    shared: Arc<(Mutex<State>, Condvar)>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Watchdog {
+    /// Starts the thread, which calls `interrupt` whenever an armed budget runs out.
+    pub fn new(interrupt: impl Fn() + Send + 'static) -> Self {
+        let shared = Arc::new((Mutex::new(State::default()), Condvar::new()));
+        let thread_shared = shared.clone();
+        let thread = thread::spawn(move || {
+            let (lock, condvar) = &*thread_shared;
+            let mut state = lock.lock().unwrap();
+            while !state.shutdown {
+                match state.deadline {
+                    None => state = condvar.wait(state).unwrap(),
+                    Some(deadline) => {
+                        let now = Instant::now();
+                        if now >= deadline {
+                            state.deadline = None;
+                            state.fired = true;
+                            interrupt();
+                        } else {
+                            state = condvar.wait_timeout(state, deadline - now).unwrap().0;
+                        }
+                    }
+                }
+            }
+        });
+
+        Watchdog {
+            shared,
+            thread: Some(thread),
+        }
+    }
+
+    /// Calls the interrupt after `budget`, unless `disarm` is called first.
+    pub fn arm(&self, budget: Duration) {
+        let (lock, condvar) = &*self.shared;
+        let mut state = lock.lock().unwrap();
+        state.deadline = Some(Instant::now() + budget);
+        state.fired = false;
+        condvar.notify_one();
+    }
+
+    /// Stops the countdown and returns whether the budget ran out before.
+    pub fn disarm(&self) -> bool {
+        let (lock, _) = &*self.shared;
+        let mut state = lock.lock().unwrap();
+        state.deadline = None;
+        std::mem::replace(&mut state.fired, false)
+    }
+
+    pub fn is_armed(&self) -> bool {
+        self.shared.0.lock().unwrap().deadline.is_some()
+    }
+}
+
+impl Drop for Watchdog {
+    fn drop(&mut self) {
+        let (lock, condvar) = &*self.shared;
+        lock.lock().unwrap().shutdown = true;
+        condvar.notify_one();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[test]
+fn interrupts_once_the_budget_runs_out() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    let interrupts = Arc::new(AtomicUsize::new(0));
+    let counter = interrupts.clone();
+    let watchdog = Watchdog::new(move || {
+        counter.fetch_add(1, Ordering::SeqCst);
+    });
+
+    watchdog.arm(Duration::from_millis(10));
+    assert!(watchdog.is_armed());
+    thread::sleep(Duration::from_millis(200));
+    assert!(!watchdog.is_armed());
+    assert!(watchdog.disarm());
+    assert_eq!(interrupts.load(Ordering::SeqCst), 1);
+
+    // Disarming in time leaves the guest alone.
+    watchdog.arm(Duration::from_secs(60));
+    assert!(!watchdog.disarm());
+    thread::sleep(Duration::from_millis(50));
+    assert_eq!(interrupts.load(Ordering::SeqCst), 1);
+}